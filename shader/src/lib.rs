@@ -8,29 +8,128 @@
 #[cfg(not(target_arch = "spirv"))]
 use spirv_std::macros::spirv;
 
-use spirv_std::glam::{vec3, vec4, Vec3, Vec4};
+use spirv_std::glam::{vec2, vec3, Mat4, Vec2, Vec3, Vec4};
+
+// Both vertex shaders write the same outputs, in location order: color, object ID, primitive ID,
+// view-space normal and texture coordinates. Fragment shaders declare the inputs they read in
+// that order, up to the last one they need.
+
+/// Push constants of `main_vs_mesh`, as column-major matrices.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct MeshConstants {
+    pub model_view: Mat4,
+    pub projection: Mat4,
+}
 
 #[spirv(fragment)]
 pub fn main_fs(output: &mut Vec4, color: Vec3) {
     *output = color.extend(1.0);
 }
 
+/// Writes albedo, a view-space normal and a screen-space motion vector to color attachments 0, 1
+/// and 2.
+#[spirv(fragment)]
+pub fn main_fs_gbuffer(
+    albedo: &mut Vec4,
+    normal_output: &mut Vec4,
+    motion: &mut Vec4,
+    color: Vec3,
+    #[spirv(flat)] _object_id: u32,
+    #[spirv(flat)] _primitive_id: u32,
+    normal: Vec3,
+) {
+    *albedo = color.extend(1.0);
+    *normal_output = normal.normalize().extend(0.0);
+    // Nothing moves between frames.
+    *motion = Vec4::ZERO;
+}
+
+/// Writes albedo, and the object and primitive IDs from the vertex shader to `R32_UINT` color
+/// attachments 1 and 2.
+#[spirv(fragment)]
+pub fn main_fs_ids(
+    albedo: &mut Vec4,
+    object_id_output: &mut u32,
+    primitive_id_output: &mut u32,
+    color: Vec3,
+    #[spirv(flat)] object_id: u32,
+    #[spirv(flat)] primitive_id: u32,
+) {
+    *albedo = color.extend(1.0);
+    *object_id_output = object_id;
+    *primitive_id_output = primitive_id;
+}
+
+/// Besides the triangle's position and color, outputs the instance index as the object ID and
+/// the triangle's index within the draw as the primitive ID, both plus one so 0 can mark the
+/// background. A draw gets its own object ID through its first instance.
+///
+/// The primitive ID is derived from the provoking vertex, which matches `gl_PrimitiveID` for
+/// non-indexed triangle lists without the `Geometry` capability that builtin needs in fragment
+/// shaders. The renderer draws geometry without an index buffer for `main_fs_ids` to keep it
+/// exact.
 #[spirv(vertex)]
 pub fn main_vs(
     #[spirv(vertex_index)] vert_id: i32,
+    #[spirv(instance_index)] instance_id: i32,
     #[spirv(position, invariant)] out_pos: &mut Vec4,
     color: &mut Vec3,
+    #[spirv(flat)] object_id: &mut u32,
+    #[spirv(flat)] primitive_id: &mut u32,
+    normal: &mut Vec3,
+    uv: &mut Vec2,
 ) {
-    *out_pos = vec4(
-        (vert_id - 1) as f32,
-        ((vert_id & 1) * 2 - 1) as f32,
-        0.0,
-        1.0,
-    );
+    let position = vec2((vert_id - 1) as f32, ((vert_id & 1) * 2 - 1) as f32);
+    *out_pos = position.extend(0.0).extend(1.0);
 
     *color = [
         vec3(1.0, 0.0, 0.0),
         vec3(0.0, 1.0, 0.0),
         vec3(0.0, 0.0, 1.0),
     ][vert_id as usize];
+
+    *object_id = instance_id as u32 + 1;
+    *primitive_id = vert_id as u32 / 3 + 1;
+
+    // The triangle faces the camera; its texture coordinates span its bounding square.
+    *normal = vec3(0.0, 0.0, 1.0);
+    *uv = position * 0.5 + 0.5;
+}
+
+/// Draws vertices with position, normal, texture coordinate and color attributes at locations 0
+/// to 3, transformed by [`MeshConstants`].
+///
+/// Colors are lit by a light at the camera, on both sides of each triangle, so shapes read without
+/// materials. IDs are computed like in `main_vs`.
+#[spirv(vertex)]
+pub fn main_vs_mesh(
+    position: Vec3,
+    vertex_normal: Vec3,
+    vertex_uv: Vec2,
+    vertex_color: Vec4,
+    #[spirv(vertex_index)] vert_id: i32,
+    #[spirv(instance_index)] instance_id: i32,
+    #[spirv(push_constant)] constants: &MeshConstants,
+    #[spirv(position, invariant)] out_pos: &mut Vec4,
+    color: &mut Vec3,
+    #[spirv(flat)] object_id: &mut u32,
+    #[spirv(flat)] primitive_id: &mut u32,
+    normal: &mut Vec3,
+    uv: &mut Vec2,
+) {
+    let view_position = constants.model_view * position.extend(1.0);
+    *out_pos = constants.projection * view_position;
+
+    let view_normal = (constants.model_view * vertex_normal.extend(0.0))
+        .truncate()
+        .normalize();
+    let light = 0.25 + 0.75 * view_normal.z.abs();
+    *color = vertex_color.truncate() * light;
+
+    *object_id = instance_id as u32 + 1;
+    *primitive_id = vert_id as u32 / 3 + 1;
+
+    *normal = view_normal;
+    *uv = vertex_uv;
 }
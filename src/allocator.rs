@@ -0,0 +1,534 @@
+//! Sub-allocation of images and buffers from large `VkDeviceMemory` blocks.
+//!
+//! Each block belongs to one memory type and is split into chunks kept sorted by offset, free
+//! neighbours being merged on release. Host-visible blocks are mapped once when created, so
+//! allocations sharing a block never map the same memory twice.
+
+use std::ptr::NonNull;
+
+use ash::vk;
+
+use crate::error::Result;
+
+/// Size of a regular block; larger resources get a dedicated block of their own.
+const DEFAULT_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Tiling class of a resource, for `bufferImageGranularity`.
+///
+/// Linear and optimal resources must not share a granularity-sized page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// Buffers and linear-tiling images.
+    Linear,
+    /// Optimal-tiling images.
+    Optimal,
+}
+
+/// Host pointer into a persistently mapped block.
+#[derive(Debug, Clone, Copy)]
+struct MappedPtr(NonNull<u8>);
+
+// The pointer is only dereferenced by whoever owns the allocation it points into.
+unsafe impl Send for MappedPtr {}
+unsafe impl Sync for MappedPtr {}
+
+/// A range of a memory block. Returned to the allocator with [`Device::free`].
+///
+/// [`Device::free`]: crate::resource::Device::free
+#[derive(Debug)]
+pub struct Allocation {
+    block_id: u64,
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    memory_type_index: u32,
+    memory_flags: vk::MemoryPropertyFlags,
+    mapped: Option<MappedPtr>,
+}
+
+impl Allocation {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    /// Offset into [`Allocation::memory`] to bind at.
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    pub fn memory_type_index(&self) -> u32 {
+        self.memory_type_index
+    }
+
+    pub fn memory_flags(&self) -> vk::MemoryPropertyFlags {
+        self.memory_flags
+    }
+
+    /// Host address of the first byte, for `HOST_VISIBLE` memory.
+    pub fn mapped_ptr(&self) -> Option<*mut u8> {
+        self.mapped.map(|ptr| ptr.0.as_ptr())
+    }
+}
+
+/// Usage of the allocator's memory blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    pub block_count: usize,
+    pub allocation_count: usize,
+    /// Bytes of device memory held in blocks.
+    pub reserved_bytes: vk::DeviceSize,
+    /// Bytes handed out to allocations, excluding alignment padding.
+    pub used_bytes: vk::DeviceSize,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    /// `None` for free chunks.
+    kind: Option<ResourceKind>,
+}
+
+impl Chunk {
+    fn end(&self) -> vk::DeviceSize {
+        self.offset + self.size
+    }
+}
+
+#[derive(Debug)]
+struct Block {
+    id: u64,
+    memory: vk::DeviceMemory,
+    memory_type_index: u32,
+    memory_flags: vk::MemoryPropertyFlags,
+    mapped: Option<MappedPtr>,
+    chunks: Vec<Chunk>,
+}
+
+impl Block {
+    /// Finds room for `size` bytes and splits the chunk it fits into.
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+        kind: ResourceKind,
+        granularity: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        let conflicts = |other: &Chunk| matches!(other.kind, Some(other) if other != kind);
+
+        let (index, offset) = self.chunks.iter().enumerate().find_map(|(i, chunk)| {
+            if chunk.kind.is_some() {
+                return None;
+            }
+
+            let mut offset = align_up(chunk.offset, alignment);
+            if let Some(previous) = i.checked_sub(1).map(|i| &self.chunks[i]) {
+                if conflicts(previous) && same_page(previous.end() - 1, offset, granularity) {
+                    offset = align_up(offset, granularity);
+                }
+            }
+
+            let end = offset + size;
+            if end > chunk.end() {
+                return None;
+            }
+            if let Some(next) = self.chunks.get(i + 1) {
+                if conflicts(next) && same_page(end - 1, next.offset, granularity) {
+                    return None;
+                }
+            }
+
+            Some((i, offset))
+        })?;
+
+        let free = self.chunks.remove(index);
+        let mut replacement = Vec::with_capacity(3);
+        if offset > free.offset {
+            replacement.push(Chunk {
+                offset: free.offset,
+                size: offset - free.offset,
+                kind: None,
+            });
+        }
+        replacement.push(Chunk {
+            offset,
+            size,
+            kind: Some(kind),
+        });
+        if offset + size < free.end() {
+            replacement.push(Chunk {
+                offset: offset + size,
+                size: free.end() - (offset + size),
+                kind: None,
+            });
+        }
+        self.chunks.splice(index..index, replacement);
+
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: vk::DeviceSize) {
+        let mut index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.offset == offset && chunk.kind.is_some())
+            .expect("allocation belongs to this block");
+        self.chunks[index].kind = None;
+
+        if index + 1 < self.chunks.len() && self.chunks[index + 1].kind.is_none() {
+            let next = self.chunks.remove(index + 1);
+            self.chunks[index].size += next.size;
+        }
+        if index > 0 && self.chunks[index - 1].kind.is_none() {
+            let chunk = self.chunks.remove(index);
+            index -= 1;
+            self.chunks[index].size += chunk.size;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chunks.iter().all(|chunk| chunk.kind.is_none())
+    }
+
+    fn size(&self) -> vk::DeviceSize {
+        self.chunks.last().map_or(0, Chunk::end)
+    }
+}
+
+/// Size of the block allocated for `size` bytes when no existing block has room: a regular block
+/// of at most an eighth of the heap, or a dedicated one for large resources.
+pub(crate) fn block_size(size: vk::DeviceSize, heap_size: vk::DeviceSize) -> vk::DeviceSize {
+    let block_size = DEFAULT_BLOCK_SIZE.min(heap_size / 8);
+    // Large resources get a block of their own instead of wasting most of a shared one.
+    if size > block_size / 2 {
+        size
+    } else {
+        block_size
+    }
+}
+
+/// `alignment` is a power of two, as are all Vulkan alignments and granularities.
+fn align_up(offset: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (offset + alignment - 1) & !(alignment - 1)
+}
+
+fn same_page(a: vk::DeviceSize, b: vk::DeviceSize, page_size: vk::DeviceSize) -> bool {
+    a / page_size == b / page_size
+}
+
+/// Block allocator owned by [`Device`](crate::resource::Device).
+#[derive(Debug)]
+pub(crate) struct BlockAllocator {
+    blocks: Vec<Block>,
+    next_block_id: u64,
+    buffer_image_granularity: vk::DeviceSize,
+}
+
+impl BlockAllocator {
+    pub fn new(buffer_image_granularity: vk::DeviceSize) -> Self {
+        Self {
+            blocks: Vec::new(),
+            next_block_id: 0,
+            buffer_image_granularity: buffer_image_granularity.max(1),
+        }
+    }
+
+    /// Sub-allocates from an existing block of `memory_type_index` or creates a new one.
+    ///
+    /// The second value is the new block's memory, if one was allocated.
+    pub fn allocate(
+        &mut self,
+        device: &ash::Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        requirements: &vk::MemoryRequirements,
+        memory_type_index: u32,
+        kind: ResourceKind,
+    ) -> Result<(Allocation, Option<vk::DeviceMemory>)> {
+        let granularity = self.buffer_image_granularity;
+        let alignment = requirements.alignment.max(1);
+
+        for block in self
+            .blocks
+            .iter_mut()
+            .filter(|block| block.memory_type_index == memory_type_index)
+        {
+            if let Some(offset) = block.allocate(requirements.size, alignment, kind, granularity) {
+                return Ok((allocation(block, offset, requirements.size), None));
+            }
+        }
+
+        let memory_type = memory_properties.memory_types[memory_type_index as usize];
+        let heap_size = memory_properties.memory_heaps[memory_type.heap_index as usize].size;
+        let block_size = block_size(requirements.size, heap_size);
+
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(block_size)
+            .memory_type_index(memory_type_index);
+        let memory = unsafe { device.allocate_memory(&allocate_info, None) }?;
+
+        let mapped = if memory_type
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+        {
+            let ptr = unsafe {
+                device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+            };
+            match ptr {
+                Ok(ptr) => NonNull::new(ptr as *mut u8).map(MappedPtr),
+                Err(err) => {
+                    unsafe { device.free_memory(memory, None) };
+                    return Err(err.into());
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut block = Block {
+            id: self.next_block_id,
+            memory,
+            memory_type_index,
+            memory_flags: memory_type.property_flags,
+            mapped,
+            chunks: vec![Chunk {
+                offset: 0,
+                size: block_size,
+                kind: None,
+            }],
+        };
+        self.next_block_id += 1;
+
+        let offset = block
+            .allocate(requirements.size, alignment, kind, granularity)
+            .expect("a new block fits the allocation");
+        let allocation = allocation(&block, offset, requirements.size);
+        self.blocks.push(block);
+
+        Ok((allocation, Some(memory)))
+    }
+
+    /// Returns `allocation` to its block, freeing the block once it is empty.
+    pub fn free(&mut self, device: &ash::Device, allocation: Allocation) {
+        let index = self
+            .blocks
+            .iter()
+            .position(|block| block.id == allocation.block_id)
+            .expect("allocation belongs to this allocator");
+
+        let block = &mut self.blocks[index];
+        block.free(allocation.offset);
+        if block.is_empty() {
+            let block = self.blocks.remove(index);
+            unsafe { device.free_memory(block.memory, None) };
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats {
+            block_count: self.blocks.len(),
+            ..AllocatorStats::default()
+        };
+        for block in &self.blocks {
+            stats.reserved_bytes += block.size();
+            for chunk in block.chunks.iter().filter(|chunk| chunk.kind.is_some()) {
+                stats.allocation_count += 1;
+                stats.used_bytes += chunk.size;
+            }
+        }
+        stats
+    }
+
+    /// Frees every block. Must only be called once nothing is bound to them anymore.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for block in self.blocks.drain(..) {
+            unsafe { device.free_memory(block.memory, None) };
+        }
+    }
+}
+
+fn allocation(block: &Block, offset: vk::DeviceSize, size: vk::DeviceSize) -> Allocation {
+    Allocation {
+        block_id: block.id,
+        memory: block.memory,
+        offset,
+        size,
+        memory_type_index: block.memory_type_index,
+        memory_flags: block.memory_flags,
+        mapped: block.mapped.map(|ptr| {
+            MappedPtr(unsafe { NonNull::new_unchecked(ptr.0.as_ptr().add(offset as usize)) })
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRANULARITY: vk::DeviceSize = 1024;
+
+    fn block(size: vk::DeviceSize) -> Block {
+        Block {
+            id: 0,
+            memory: vk::DeviceMemory::null(),
+            memory_type_index: 0,
+            memory_flags: vk::MemoryPropertyFlags::empty(),
+            mapped: None,
+            chunks: vec![Chunk {
+                offset: 0,
+                size,
+                kind: None,
+            }],
+        }
+    }
+
+    /// `(offset, size, kind)` of every chunk.
+    fn layout(block: &Block) -> Vec<(vk::DeviceSize, vk::DeviceSize, Option<ResourceKind>)> {
+        block
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.offset, chunk.size, chunk.kind))
+            .collect()
+    }
+
+    #[test]
+    fn same_kind_neighbours_share_a_page() {
+        let mut block = block(4096);
+
+        assert_eq!(
+            block.allocate(100, 4, ResourceKind::Linear, GRANULARITY),
+            Some(0)
+        );
+        assert_eq!(
+            block.allocate(100, 4, ResourceKind::Linear, GRANULARITY),
+            Some(100)
+        );
+        assert_eq!(
+            block.allocate(100, 256, ResourceKind::Linear, GRANULARITY),
+            Some(256)
+        );
+    }
+
+    #[test]
+    fn optimal_after_linear_starts_on_the_next_page() {
+        let mut block = block(4096);
+
+        assert_eq!(
+            block.allocate(100, 4, ResourceKind::Linear, GRANULARITY),
+            Some(0)
+        );
+        assert_eq!(
+            block.allocate(100, 256, ResourceKind::Optimal, GRANULARITY),
+            Some(GRANULARITY)
+        );
+        // The padding before the image stays free.
+        assert_eq!(
+            layout(&block),
+            [
+                (0, 100, Some(ResourceKind::Linear)),
+                (100, GRANULARITY - 100, None),
+                (GRANULARITY, 100, Some(ResourceKind::Optimal)),
+                (GRANULARITY + 100, 4096 - GRANULARITY - 100, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn optimal_before_linear_must_end_a_page_earlier() {
+        let mut block = block(4096);
+        block.allocate(100, 4, ResourceKind::Linear, GRANULARITY);
+        block.allocate(100, 4, ResourceKind::Linear, GRANULARITY);
+        block.free(0);
+
+        // The free gap at 0 shares the first page with the buffer at 100.
+        assert_eq!(
+            block.allocate(50, 4, ResourceKind::Optimal, GRANULARITY),
+            Some(GRANULARITY)
+        );
+        // A buffer still fits into it.
+        assert_eq!(
+            block.allocate(50, 4, ResourceKind::Linear, GRANULARITY),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn granularity_of_one_ignores_kinds() {
+        let mut block = block(4096);
+
+        block.allocate(100, 4, ResourceKind::Linear, 1);
+        assert_eq!(block.allocate(100, 4, ResourceKind::Optimal, 1), Some(100));
+    }
+
+    #[test]
+    fn full_block_rejects_allocations() {
+        let mut block = block(1024);
+
+        assert_eq!(
+            block.allocate(1024, 4, ResourceKind::Linear, GRANULARITY),
+            Some(0)
+        );
+        assert_eq!(
+            block.allocate(1, 1, ResourceKind::Linear, GRANULARITY),
+            None
+        );
+    }
+
+    #[test]
+    fn frees_merge_with_free_neighbours() {
+        let mut block = block(300);
+        for _ in 0..3 {
+            block.allocate(100, 4, ResourceKind::Linear, GRANULARITY);
+        }
+
+        block.free(100);
+        assert_eq!(
+            layout(&block),
+            [
+                (0, 100, Some(ResourceKind::Linear)),
+                (100, 100, None),
+                (200, 100, Some(ResourceKind::Linear)),
+            ]
+        );
+
+        // Merges with the free chunk after it.
+        block.free(0);
+        assert_eq!(
+            layout(&block),
+            [(0, 200, None), (200, 100, Some(ResourceKind::Linear))]
+        );
+
+        // Merges with the free chunk before it.
+        block.free(200);
+        assert_eq!(layout(&block), [(0, 300, None)]);
+        assert!(block.is_empty());
+        assert_eq!(block.size(), 300);
+    }
+
+    #[test]
+    fn freed_space_is_reused() {
+        let mut block = block(300);
+        for _ in 0..3 {
+            block.allocate(100, 4, ResourceKind::Linear, GRANULARITY);
+        }
+        block.free(100);
+
+        assert_eq!(
+            block.allocate(100, 4, ResourceKind::Linear, GRANULARITY),
+            Some(100)
+        );
+        assert_eq!(block.chunks.len(), 3);
+    }
+
+    #[test]
+    fn block_size_depends_on_heap_and_resource() {
+        const MIB: vk::DeviceSize = 1024 * 1024;
+
+        assert_eq!(block_size(MIB, 8 * 1024 * MIB), DEFAULT_BLOCK_SIZE);
+        assert_eq!(block_size(MIB, 128 * MIB), 16 * MIB);
+        assert_eq!(block_size(40 * MIB, 8 * 1024 * MIB), 40 * MIB);
+    }
+}
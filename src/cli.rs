@@ -0,0 +1,649 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use ash::vk;
+
+use ash_offscreen_rendering_example::{
+    gbuffer_attachments, id_attachments, parse_clear_color, parse_color_format,
+    parse_debug_message_types, parse_debug_severity, parse_depth_format, parse_device_selector,
+    parse_sample_count, ColorAttachmentConfig, DepthConfig, OutputFormat, RenderConfig, Validation,
+    GBUFFER_ENTRY_POINT, ID_ENTRY_POINT, ID_FORMAT, MESH_ENTRY_POINT, SUPPORTED_COLOR_FORMATS,
+    SUPPORTED_DEPTH_FORMATS,
+};
+
+pub const USAGE: &str = "\
+Render a triangle, or a model, offscreen with Vulkan and save it as an image.
+
+USAGE:
+    ash-offscreen-rendering-example [OPTIONS] [MODEL]
+    ash-offscreen-rendering-example --list-devices [--json] [--device <SELECTOR>] [--validation]
+
+ARGS:
+    <MODEL>                      OBJ (with MTL), STL or PLY model to render instead of the
+                                 triangle, framed by its bounding box with depth testing. Normals
+                                 are generated where the file has none
+
+OPTIONS:
+    -W, --width <PIXELS>         Image width [default: 800]
+    -H, --height <PIXELS>        Image height [default: 600]
+    -o, --output <PATH>          Output file, or - for stdout [default: out.png]
+        --output-format <png|jpeg|webp|tga|ppm|raw|exr|hdr>
+                                 Output encoding; exr and hdr keep float values [default: from
+                                 the --output extension, PNG for stdout]
+    -q, --quality <1-100>        JPEG quality [default: 90]
+    -f, --format <FORMAT>        Color attachment format: R8G8B8A8_UNORM, B8G8R8A8_UNORM,
+                                 R8G8B8A8_SRGB, R16G16B16A16_UNORM, R16G16B16A16_SFLOAT or
+                                 R32G32B32A32_SFLOAT. Formats with more than 8 bits per channel
+                                 are saved as 16-bit PNG [default: R8G8B8A8_SRGB, or
+                                 R8G8B8A8_UNORM where the device lacks it]
+    -c, --clear-color <R,G,B[,A]>
+                                 Linear clear color; components outside 0.0..=1.0 are kept by
+                                 the float formats only [default: 0,0,0,1]
+    -d, --device <INDEX|NAME|UUID>
+                                 Physical device by index, name substring or UUID
+                                 [default: $OFFSCREEN_DEVICE, else the best scoring device]
+        --validation             Enable VK_LAYER_KHRONOS_validation if installed
+                                 [default in debug builds]
+        --require-validation     Fail if VK_LAYER_KHRONOS_validation is not installed
+        --no-validation          Disable VK_LAYER_KHRONOS_validation
+        --debug-severity <verbose|info|warning|error>
+                                 Least severe validation message to log [default: warning]
+        --debug-types <general,validation,performance>
+                                 Validation message types to log [default: all]
+        --fail-on-validation-error
+                                 Exit with an error if validation reports an error
+        --readback <buffer|linear-image>
+                                 Copy the image to the host through a staging buffer or a
+                                 linear-tiled image [default: buffer]
+        --gbuffer                Also render view-space normals and motion vectors with the
+                                 built-in main_fs_gbuffer, saved next to the output as
+                                 <NAME>.normal.<EXT> and <NAME>.motion.<EXT>
+        --ids                    Also render object and primitive IDs with the built-in
+                                 main_fs_ids, saved next to the output as color-coded
+                                 <NAME>.object_id.<EXT> and <NAME>.primitive_id.<EXT> with JSON
+                                 legends; raw output keeps the 32-bit IDs
+        --attachment <NAME:FORMAT>
+                                 Add a color attachment at the next fragment output location,
+                                 saved next to the output as <NAME>.<ATTACHMENT>.<EXT>; repeatable.
+                                 R32_UINT attachments are saved like --ids
+        --vertex-entry <NAME>    Vertex shader entry point [default: main_vs, main_vs_mesh with
+                                 a MODEL]
+        --fragment-entry <NAME>  Fragment shader entry point [default: main_fs]
+        --msaa <1|2|4|8>         Samples per pixel for anti-aliasing, lowered to what the device
+                                 supports [default: 1]
+        --sample-shading <FRACTION>
+                                 Shade at least this fraction of each pixel's samples
+                                 individually; needs the sample_rate_shading feature
+        --depth                  Add a depth attachment and enable depth testing
+        --depth-stencil          Like --depth, with a stencil aspect
+        --depth-format <FORMAT>  Depth format to use instead of the first supported of D32_SFLOAT,
+                                 X8_D24_UNORM_PACK32 and D16_UNORM; implies --depth
+        --depth-output <PATH>    Also save the depth map as a 16-bit PNG, a float EXR or raw
+                                 32-bit floats (.png, .exr or .raw); implies --depth
+    -s, --shader <PATH>          SPIR-V module with the vertex and fragment entry points
+        --list-devices           Print layers, extensions and every physical device instead of
+                                 rendering
+        --json                   Print the --list-devices report as JSON
+    -h, --help                   Print this message
+";
+
+#[derive(Debug)]
+pub enum Command {
+    /// An `output` of `-` means stdout.
+    Render {
+        config: RenderConfig,
+        output: PathBuf,
+        format: OutputFormat,
+        depth_output: Option<(PathBuf, OutputFormat)>,
+        /// Where each of `config.extra_color_attachments` is saved, in `format`.
+        attachment_outputs: Vec<PathBuf>,
+        /// Model file to load into `config.geometry` before rendering.
+        model: Option<PathBuf>,
+    },
+    /// The device report only uses the validation and device settings of `config`.
+    ListDevices {
+        config: RenderConfig,
+        json: bool,
+    },
+    Help,
+}
+
+/// Invalid command line; the message is meant to be shown next to the usage hint.
+#[derive(Debug)]
+pub struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, UsageError> {
+    let mut config = RenderConfig::default();
+    let mut output = PathBuf::from("out.png");
+    let mut output_format = None;
+    let mut quality = None;
+    let mut depth = false;
+    let mut depth_stencil = false;
+    let mut depth_format = None;
+    let mut depth_output = None;
+    let mut gbuffer = false;
+    let mut ids = false;
+    let mut vertex_entry_point = None;
+    let mut model = None;
+    let mut list_devices = false;
+    let mut json = false;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`.
+        let (flag, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| UsageError(format!("{} requires a value", flag)))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-W" | "--width" => config.width = parse_extent(&flag, &value()?)?,
+            "-H" | "--height" => config.height = parse_extent(&flag, &value()?)?,
+            "-o" | "--output" => output = PathBuf::from(value()?),
+            "--output-format" => {
+                let name = value()?;
+                output_format = Some(name.parse().map_err(|()| {
+                    UsageError(format!(
+                        "unsupported output format '{}', expected one of: {}",
+                        name,
+                        OutputFormat::NAMES.join(", ")
+                    ))
+                })?);
+            }
+            "-q" | "--quality" => {
+                let value = value()?;
+                quality = match value.parse() {
+                    Ok(quality @ 1..=100) => Some(quality),
+                    _ => {
+                        return Err(UsageError(format!(
+                            "{} expects an integer between 1 and 100, got '{}'",
+                            flag, value
+                        )))
+                    }
+                };
+            }
+            "-f" | "--format" => config.color_format = color_format(&value()?)?,
+            "-c" | "--clear-color" => {
+                let color = value()?;
+                config.clear_color = parse_clear_color(&color).ok_or_else(|| {
+                    UsageError(format!(
+                        "invalid clear color '{}', expected R,G,B or R,G,B,A such as 0.1,0.2,0.3",
+                        color
+                    ))
+                })?;
+            }
+            "-d" | "--device" => config.device = Some(parse_device_selector(&value()?)),
+            "--validation" => config.validation = Validation::Optional,
+            "--require-validation" => config.validation = Validation::Required,
+            "--no-validation" => config.validation = Validation::Disabled,
+            "--debug-severity" => {
+                let severity = value()?;
+                config.debug_messenger.severity =
+                    parse_debug_severity(&severity).ok_or_else(|| {
+                        UsageError(format!(
+                            "invalid debug severity '{}', expected verbose, info, warning or error",
+                            severity
+                        ))
+                    })?;
+            }
+            "--debug-types" => {
+                let types = value()?;
+                config.debug_messenger.message_types =
+                    parse_debug_message_types(&types).ok_or_else(|| {
+                        UsageError(format!(
+                            "invalid debug types '{}', expected a list of general, validation and performance",
+                            types
+                        ))
+                    })?;
+            }
+            "--fail-on-validation-error" => config.debug_messenger.fail_on_error = true,
+            "--readback" => {
+                let method = value()?;
+                config.readback = method.parse().map_err(|()| {
+                    UsageError(format!(
+                        "invalid readback method '{}', expected buffer or linear-image",
+                        method
+                    ))
+                })?;
+            }
+            "--gbuffer" => {
+                gbuffer = true;
+                config.extra_color_attachments.extend(gbuffer_attachments());
+                config.fragment_entry_point = GBUFFER_ENTRY_POINT.to_string();
+            }
+            "--ids" => {
+                ids = true;
+                config.extra_color_attachments.extend(id_attachments());
+                config.fragment_entry_point = ID_ENTRY_POINT.to_string();
+            }
+            "--attachment" => {
+                let value = value()?;
+                let (name, format) = match value.find(':') {
+                    Some(i) if i > 0 => (&value[..i], attachment_format(&value[i + 1..])?),
+                    _ => {
+                        return Err(UsageError(format!(
+                            "{} expects NAME:FORMAT such as normal:R16G16B16A16_SFLOAT, got '{}'",
+                            flag, value
+                        )))
+                    }
+                };
+                config
+                    .extra_color_attachments
+                    .push(ColorAttachmentConfig::new(name, format));
+            }
+            "--vertex-entry" => vertex_entry_point = Some(value()?),
+            "--fragment-entry" => config.fragment_entry_point = value()?,
+            "--msaa" => {
+                let count = value()?;
+                config.multisample.samples = parse_sample_count(&count).ok_or_else(|| {
+                    UsageError(format!(
+                        "invalid sample count '{}', expected 1, 2, 4 or 8",
+                        count
+                    ))
+                })?;
+            }
+            "--sample-shading" => {
+                let value = value()?;
+                config.multisample.min_sample_shading = match value.parse::<f32>() {
+                    Ok(fraction) if (0.0..=1.0).contains(&fraction) => Some(fraction),
+                    _ => {
+                        return Err(UsageError(format!(
+                            "{} expects a number between 0 and 1, got '{}'",
+                            flag, value
+                        )))
+                    }
+                };
+            }
+            "--depth" => depth = true,
+            "--depth-stencil" => depth_stencil = true,
+            "--depth-format" => {
+                let name = value()?;
+                depth_format = Some(parse_depth_format(&name).ok_or_else(|| {
+                    let supported: Vec<&str> =
+                        SUPPORTED_DEPTH_FORMATS.iter().map(|&(n, _)| n).collect();
+                    UsageError(format!(
+                        "unsupported depth format '{}', expected one of: {}",
+                        name,
+                        supported.join(", ")
+                    ))
+                })?);
+            }
+            "--depth-output" => depth_output = Some(PathBuf::from(value()?)),
+            "-s" | "--shader" => config.shader = Some(PathBuf::from(value()?)),
+            "--list-devices" => list_devices = true,
+            "--json" => json = true,
+            _ if flag.starts_with('-') => {
+                return Err(UsageError(format!("unknown option '{}'", flag)))
+            }
+            _ if model.is_none() => model = Some(PathBuf::from(arg)),
+            _ => return Err(UsageError(format!("unexpected argument '{}'", arg))),
+        }
+    }
+
+    // Both pick the fragment shader, which can only write one set of outputs.
+    if gbuffer && ids {
+        return Err(UsageError(
+            "--gbuffer and --ids cannot be combined".to_string(),
+        ));
+    }
+
+    // Models need depth testing to hide their back, and their own vertex shader.
+    if depth || model.is_some() || depth_stencil || depth_format.is_some() || depth_output.is_some()
+    {
+        let mut depth_config = if depth_stencil {
+            DepthConfig::depth_stencil()
+        } else {
+            DepthConfig::default()
+        };
+        if let Some(format) = depth_format {
+            depth_config.formats = vec![format];
+        }
+        depth_config.readback = depth_output.is_some();
+        config.depth = Some(depth_config);
+    }
+    config.vertex_entry_point = match vertex_entry_point {
+        Some(name) => name,
+        None if model.is_some() => MESH_ENTRY_POINT.to_string(),
+        None => config.vertex_entry_point,
+    };
+
+    if list_devices {
+        Ok(Command::ListDevices { config, json })
+    } else if json {
+        Err(UsageError("--json requires --list-devices".to_string()))
+    } else {
+        let format = output_format_for(&output, output_format, quality)?;
+        let depth_output = depth_output
+            .map(|path| {
+                let format = depth_output_format(&path)?;
+                Ok((path, format))
+            })
+            .transpose()?;
+        let attachment_outputs = attachment_outputs(&output, format, &config)?;
+        Ok(Command::Render {
+            config,
+            output,
+            format,
+            depth_output,
+            attachment_outputs,
+            model,
+        })
+    }
+}
+
+/// Picks the explicit format, else the one matching the extension of `output`, applying the JPEG
+/// `quality`.
+fn output_format_for(
+    output: &Path,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+) -> Result<OutputFormat, UsageError> {
+    let format = match format {
+        Some(format) => format,
+        None if output == Path::new("-") => OutputFormat::Png,
+        None => OutputFormat::from_extension(output).ok_or_else(|| {
+            UsageError(format!(
+                "cannot tell the output format from '{}', pass --output-format",
+                output.display()
+            ))
+        })?,
+    };
+
+    match (format, quality) {
+        (OutputFormat::Jpeg { .. }, Some(quality)) => Ok(OutputFormat::Jpeg { quality }),
+        (_, Some(_)) => Err(UsageError(format!(
+            "--quality only applies to JPEG output, not {}",
+            format
+        ))),
+        (format, None) => Ok(format),
+    }
+}
+
+/// Paths for the extra color attachments: `out.png` becomes `out.normal.png` for an attachment
+/// named `normal`.
+fn attachment_outputs(
+    output: &Path,
+    format: OutputFormat,
+    config: &RenderConfig,
+) -> Result<Vec<PathBuf>, UsageError> {
+    let attachments = &config.extra_color_attachments;
+    if attachments.is_empty() {
+        return Ok(Vec::new());
+    }
+    if output == Path::new("-") {
+        return Err(UsageError(
+            "extra color attachments cannot be written to stdout".to_string(),
+        ));
+    }
+
+    let mut paths: Vec<PathBuf> = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = output
+            .extension()
+            .map_or_else(|| format.extension().into(), |ext| ext.to_string_lossy());
+        let path = output.with_file_name(format!("{}.{}.{}", stem, attachment.name, extension));
+
+        if paths.contains(&path) {
+            return Err(UsageError(format!(
+                "more than one attachment is named '{}'",
+                attachment.name
+            )));
+        }
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+fn color_format(name: &str) -> Result<vk::Format, UsageError> {
+    parse_color_format(name).ok_or_else(|| {
+        let supported: Vec<&str> = SUPPORTED_COLOR_FORMATS.iter().map(|&(n, _)| n).collect();
+        UsageError(format!(
+            "unsupported format '{}', expected one of: {}",
+            name,
+            supported.join(", ")
+        ))
+    })
+}
+
+fn attachment_format(name: &str) -> Result<vk::Format, UsageError> {
+    if name.eq_ignore_ascii_case("R32_UINT") {
+        Ok(ID_FORMAT)
+    } else {
+        color_format(name)
+    }
+}
+
+fn depth_output_format(path: &Path) -> Result<OutputFormat, UsageError> {
+    match OutputFormat::from_extension(path) {
+        Some(format) if format.stores_depth() => Ok(format),
+        _ => Err(UsageError(format!(
+            "cannot save depth as '{}', expected a .png, .exr or .raw file",
+            path.display()
+        ))),
+    }
+}
+
+fn parse_extent(flag: &str, value: &str) -> Result<u32, UsageError> {
+    match value.parse() {
+        Ok(pixels) if pixels > 0 => Ok(pixels),
+        _ => Err(UsageError(format!(
+            "{} expects a positive integer, got '{}'",
+            flag, value
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, UsageError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    /// The render command for `args`, which must parse.
+    fn render(args: &[&str]) -> (RenderConfig, PathBuf, OutputFormat, Vec<PathBuf>) {
+        match parse(args) {
+            Ok(Command::Render {
+                config,
+                output,
+                format,
+                attachment_outputs,
+                ..
+            }) => (config, output, format, attachment_outputs),
+            other => panic!("expected a render command for {:?}, got {:?}", args, other),
+        }
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(err) => err.to_string(),
+            Ok(command) => panic!("expected {:?} to fail, got {:?}", args, command),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let (config, output, format, attachment_outputs) = render(&[]);
+
+        assert_eq!(config, RenderConfig::default());
+        assert_eq!(output, PathBuf::from("out.png"));
+        assert_eq!(format, OutputFormat::Png);
+        assert!(attachment_outputs.is_empty());
+    }
+
+    #[test]
+    fn flag_values() {
+        let (config, output, format, _) = render(&[
+            "-W",
+            "1920",
+            "--height=1080",
+            "--format",
+            "b8g8r8a8_unorm",
+            "-c",
+            "0.1, 0.2,0.3",
+            "-o",
+            "triangle.jpg",
+            "--quality=85",
+        ]);
+
+        assert_eq!((config.width, config.height), (1920, 1080));
+        assert_eq!(config.color_format, vk::Format::B8G8R8A8_UNORM);
+        // Parsed like the literals, so the bits match exactly.
+        let clear_color: Vec<u32> = config.clear_color.iter().map(|c| c.to_bits()).collect();
+        assert_eq!(
+            clear_color,
+            [
+                0.1f32.to_bits(),
+                0.2f32.to_bits(),
+                0.3f32.to_bits(),
+                1.0f32.to_bits()
+            ]
+        );
+        assert_eq!(output, PathBuf::from("triangle.jpg"));
+        assert_eq!(format, OutputFormat::Jpeg { quality: 85 });
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(error(&["--width"]), "--width requires a value");
+        assert_eq!(
+            error(&["--width", "0"]),
+            "--width expects a positive integer, got '0'"
+        );
+        assert!(error(&["-c", "1,2"]).starts_with("invalid clear color '1,2'"));
+        assert!(error(&["-f", "R8_UNORM"]).starts_with("unsupported format 'R8_UNORM'"));
+        assert_eq!(error(&["--bogus"]), "unknown option '--bogus'");
+    }
+
+    #[test]
+    fn help() {
+        assert!(matches!(parse(&["-W", "5", "--help"]), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn list_devices() {
+        assert!(matches!(
+            parse(&["--list-devices", "--json"]),
+            Ok(Command::ListDevices { json: true, .. })
+        ));
+        assert_eq!(error(&["--json"]), "--json requires --list-devices");
+    }
+
+    #[test]
+    fn stdout_output() {
+        let (_, output, format, _) = render(&["-o", "-"]);
+        assert_eq!(output, PathBuf::from("-"));
+        assert_eq!(format, OutputFormat::Png);
+
+        let (_, _, format, _) = render(&["-o", "-", "--output-format", "webp"]);
+        assert_eq!(format, OutputFormat::WebP);
+
+        assert_eq!(
+            error(&["-o", "-", "--attachment", "normal:R16G16B16A16_SFLOAT"]),
+            "extra color attachments cannot be written to stdout"
+        );
+    }
+
+    #[test]
+    fn output_format_conflicts() {
+        assert!(error(&["-o", "out.bmp"]).starts_with("cannot tell the output format"));
+        assert_eq!(
+            error(&["-q", "50"]),
+            "--quality only applies to JPEG output, not PNG"
+        );
+    }
+
+    #[test]
+    fn attachments() {
+        let (config, _, _, attachment_outputs) = render(&[
+            "-o",
+            "frame.exr",
+            "--attachment",
+            "normal:R16G16B16A16_SFLOAT",
+            "--attachment=mask:r32_uint",
+        ]);
+
+        let attachments: Vec<_> = config
+            .extra_color_attachments
+            .iter()
+            .map(|attachment| (attachment.name.as_str(), attachment.format))
+            .collect();
+        assert_eq!(
+            attachments,
+            [
+                ("normal", vk::Format::R16G16B16A16_SFLOAT),
+                ("mask", ID_FORMAT)
+            ]
+        );
+        assert_eq!(
+            attachment_outputs,
+            [
+                PathBuf::from("frame.normal.exr"),
+                PathBuf::from("frame.mask.exr")
+            ]
+        );
+
+        assert_eq!(
+            error(&["--gbuffer", "--ids"]),
+            "--gbuffer and --ids cannot be combined"
+        );
+        assert!(error(&["--attachment", ":R8G8B8A8_UNORM"]).contains("expects NAME:FORMAT"));
+        assert!(error(&["--attachment", "normal"]).contains("expects NAME:FORMAT"));
+        assert_eq!(
+            error(&[
+                "--attachment",
+                "a:R8G8B8A8_UNORM",
+                "--attachment",
+                "a:R8G8B8A8_SRGB"
+            ]),
+            "more than one attachment is named 'a'"
+        );
+    }
+
+    #[test]
+    fn depth() {
+        let (config, ..) = render(&[]);
+        assert_eq!(config.depth, None);
+
+        let (config, ..) = render(&["--depth-format", "D16_UNORM"]);
+        let depth = config.depth.expect("--depth-format implies --depth");
+        assert_eq!(depth.formats, [vk::Format::D16_UNORM]);
+        assert!(!depth.readback);
+
+        assert!(error(&["--depth-output", "depth.jpg"]).starts_with("cannot save depth"));
+    }
+
+    #[test]
+    fn model() {
+        let (config, ..) = render(&["model.obj", "-o", "out.png"]);
+        assert!(config.depth.is_some());
+        assert_eq!(config.vertex_entry_point, MESH_ENTRY_POINT);
+
+        let (config, ..) = render(&["model.obj", "--vertex-entry", "custom_vs"]);
+        assert_eq!(config.vertex_entry_point, "custom_vs");
+
+        assert_eq!(error(&["a.obj", "b.obj"]), "unexpected argument 'b.obj'");
+    }
+}
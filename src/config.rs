@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+
+use ash::vk;
+
+use crate::{
+    debug::DebugMessengerConfig, depth::DepthConfig, device::DeviceSelector,
+    features::DeviceRequirements, geometry::Geometry, instance::Validation,
+    multisample::MultisampleConfig, readback::ReadbackMethod,
+};
+
+/// Color attachment formats the readback path knows how to convert to RGBA, by name.
+///
+/// `SRGB` formats store the shader's linear output sRGB-encoded, the others store it as is. The
+/// 16-bit and float formats keep more precision; read them with
+/// [`OffscreenRenderer::render_rgba16`](crate::OffscreenRenderer::render_rgba16) or, for values
+/// outside `0.0..=1.0`, [`OffscreenRenderer::render_hdr`](crate::OffscreenRenderer::render_hdr).
+pub const SUPPORTED_COLOR_FORMATS: &[(&str, vk::Format)] = &[
+    ("R8G8B8A8_UNORM", vk::Format::R8G8B8A8_UNORM),
+    ("B8G8R8A8_UNORM", vk::Format::B8G8R8A8_UNORM),
+    ("R8G8B8A8_SRGB", vk::Format::R8G8B8A8_SRGB),
+    ("R16G16B16A16_UNORM", vk::Format::R16G16B16A16_UNORM),
+    ("R16G16B16A16_SFLOAT", vk::Format::R16G16B16A16_SFLOAT),
+    ("R32G32B32A32_SFLOAT", vk::Format::R32G32B32A32_SFLOAT),
+];
+
+/// Format of attachments holding integer IDs, read back with
+/// [`MappedImage::to_id_image`](crate::MappedImage::to_id_image).
+pub const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+/// Fragment shader entry point of the built-in shader that writes albedo to the first color
+/// attachment and the attachments of [`gbuffer_attachments`] after it.
+pub const GBUFFER_ENTRY_POINT: &str = "main_fs_gbuffer";
+
+/// Fragment shader entry point of the built-in shader that writes albedo to the first color
+/// attachment and the attachments of [`id_attachments`] after it.
+pub const ID_ENTRY_POINT: &str = "main_fs_ids";
+
+/// A color attachment after the first one, see [`RenderConfig::extra_color_attachments`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorAttachmentConfig {
+    /// Names the attachment's Vulkan objects, and its output file on the command line.
+    pub name: String,
+    /// One of [`SUPPORTED_COLOR_FORMATS`], or [`ID_FORMAT`].
+    pub format: vk::Format,
+    /// Value the attachment is cleared to; converted to integers for [`ID_FORMAT`].
+    pub clear_color: [f32; 4],
+}
+
+impl ColorAttachmentConfig {
+    /// An attachment cleared to zero.
+    pub fn new(name: &str, format: vk::Format) -> Self {
+        Self {
+            name: name.to_string(),
+            format,
+            clear_color: [0.0; 4],
+        }
+    }
+}
+
+/// The attachments [`GBUFFER_ENTRY_POINT`] writes after albedo: view-space normals and
+/// screen-space motion vectors, both as half floats so negative values survive.
+pub fn gbuffer_attachments() -> Vec<ColorAttachmentConfig> {
+    vec![
+        ColorAttachmentConfig::new("normal", vk::Format::R16G16B16A16_SFLOAT),
+        ColorAttachmentConfig::new("motion", vk::Format::R16G16B16A16_SFLOAT),
+    ]
+}
+
+/// The attachments [`ID_ENTRY_POINT`] writes after albedo: the object ID of each draw, taken from
+/// its instance index, and the index of each triangle within its draw, both plus one.
+pub fn id_attachments() -> Vec<ColorAttachmentConfig> {
+    vec![
+        ColorAttachmentConfig::new("object_id", ID_FORMAT),
+        ColorAttachmentConfig::new("primitive_id", ID_FORMAT),
+    ]
+}
+
+/// Settings the instance, device, render targets and pipeline are built from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Format of the first color attachment, one of [`SUPPORTED_COLOR_FORMATS`].
+    /// `R8G8B8A8_SRGB`, the default, falls back to `R8G8B8A8_UNORM` on devices that cannot render
+    /// to it and read it back; [`OffscreenRenderer::config`](crate::OffscreenRenderer::config)
+    /// has the format in use.
+    pub color_format: vk::Format,
+    /// Linear RGBA value the first color attachment is cleared to.
+    pub clear_color: [f32; 4],
+    /// Color attachments written by the fragment shader outputs at locations 1, 2 and so on,
+    /// after the first one at location 0.
+    pub extra_color_attachments: Vec<ColorAttachmentConfig>,
+    /// Depth attachment and depth testing; `None` renders without them.
+    pub depth: Option<DepthConfig>,
+    /// Multisample anti-aliasing; one sample per pixel by default.
+    pub multisample: MultisampleConfig,
+    /// Falls back to [`DeviceSelector::from_env`] and then to the highest scoring device when
+    /// `None`.
+    pub device: Option<DeviceSelector>,
+    /// Features and extensions the device must or may provide.
+    pub device_requirements: DeviceRequirements,
+    /// Whether to enable `VK_LAYER_KHRONOS_validation`.
+    pub validation: Validation,
+    /// Severities and types of validation and driver messages forwarded to the `log` crate.
+    pub debug_messenger: DebugMessengerConfig,
+    /// How the color attachments are copied to host memory.
+    pub readback: ReadbackMethod,
+    /// Triangles uploaded into vertex and index buffers, drawn instead of the triangle `main_vs`
+    /// generates. Pair [`MeshVertex`](crate::MeshVertex) geometry with
+    /// [`MESH_ENTRY_POINT`](crate::MESH_ENTRY_POINT).
+    pub geometry: Option<Geometry>,
+    /// SPIR-V module with the vertex and fragment entry points to use instead of the built-in one.
+    pub shader: Option<PathBuf>,
+    /// Vertex shader entry point, which reads the attributes of `geometry` if there is any.
+    pub vertex_entry_point: String,
+    /// Fragment shader entry point, which writes one output per color attachment.
+    pub fragment_entry_point: String,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            color_format: vk::Format::R8G8B8A8_SRGB,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            extra_color_attachments: Vec::new(),
+            depth: None,
+            multisample: MultisampleConfig::default(),
+            device: None,
+            device_requirements: DeviceRequirements::default(),
+            validation: Validation::default(),
+            debug_messenger: DebugMessengerConfig::default(),
+            readback: ReadbackMethod::default(),
+            geometry: None,
+            shader: None,
+            vertex_entry_point: "main_vs".to_string(),
+            fragment_entry_point: "main_fs".to_string(),
+        }
+    }
+}
+
+impl RenderConfig {
+    /// Formats of all color attachments, in location order.
+    pub fn color_formats(&self) -> Vec<vk::Format> {
+        std::iter::once(self.color_format)
+            .chain(
+                self.extra_color_attachments
+                    .iter()
+                    .map(|attachment| attachment.format),
+            )
+            .collect()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// Looks up a color format by its Vulkan name without the `VK_FORMAT_` prefix, ignoring case.
+pub fn parse_color_format(name: &str) -> Option<vk::Format> {
+    SUPPORTED_COLOR_FORMATS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, format)| format)
+}
+
+/// Parses `r,g,b` or `r,g,b,a` as floats; alpha defaults to `1.0`.
+pub fn parse_clear_color(s: &str) -> Option<[f32; 4]> {
+    let components = s
+        .split(',')
+        .map(|c| c.trim().parse::<f32>().ok())
+        .collect::<Option<Vec<f32>>>()?;
+
+    match components.len() {
+        3 | 4 => {
+            let mut color = [1.0; 4];
+            color[..components.len()].copy_from_slice(&components);
+            Some(color)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,410 @@
+//! `VK_EXT_debug_utils` messenger that forwards driver and layer messages to the `log` crate.
+//!
+//! Messages are logged with targets `vulkan::validation`, `vulkan::performance` and
+//! `vulkan::general`, so they can be filtered like any other log output and picked up by
+//! `tracing` subscribers through `tracing-log`. With capture enabled they are also collected in
+//! a [`ValidationSink`] passed to the callback as `pUserData`.
+
+use std::{
+    borrow::Cow,
+    ffi::{c_void, CStr},
+    fmt::{self, Write},
+    os::raw::c_char,
+    slice,
+    sync::Mutex,
+};
+
+use ash::vk;
+use log::{Level, LevelFilter};
+
+/// Which messages the debug messenger reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugMessengerConfig {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Also collect reported messages for
+    /// [`OffscreenRenderer::take_validation_messages`](crate::OffscreenRenderer::take_validation_messages).
+    pub capture: bool,
+    /// Fail the next render with [`RenderError::Validation`](crate::RenderError::Validation)
+    /// once an error has been reported. Errors are captured for this even without `capture`.
+    pub fail_on_error: bool,
+}
+
+impl Default for DebugMessengerConfig {
+    /// Warnings and errors of every type.
+    fn default() -> Self {
+        Self {
+            severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_types: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            capture: false,
+            fail_on_error: false,
+        }
+    }
+}
+
+impl DebugMessengerConfig {
+    pub fn captures(&self) -> bool {
+        self.capture || self.fail_on_error
+    }
+
+    /// `sink` has to outlive the messenger created from the returned create info.
+    pub(crate) fn create_info(
+        &self,
+        sink: Option<&ValidationSink>,
+    ) -> vk::DebugUtilsMessengerCreateInfoEXT {
+        let user_data = sink.map_or(std::ptr::null_mut(), |sink| {
+            sink as *const ValidationSink as *mut c_void
+        });
+
+        vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(self.severity)
+            .message_type(self.message_types)
+            .pfn_user_callback(Some(vulkan_debug_utils_callback))
+            .user_data(user_data)
+            .build()
+    }
+
+    /// The most verbose log level the selected severities are logged at.
+    pub fn max_log_level(&self) -> LevelFilter {
+        [
+            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        ]
+        .iter()
+        .find(|&&severity| self.severity.contains(severity))
+        .map_or(LevelFilter::Off, |&severity| {
+            severity_level(severity).to_level_filter()
+        })
+    }
+}
+
+/// A message reported to the debug messenger while capture is enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Identifier such as `VUID-vkCmdDraw-None-02859`, if the layer provided one.
+    pub message_id_name: Option<String>,
+    pub message_id_number: i32,
+    /// Message text followed by the labels and objects it refers to.
+    pub message: String,
+}
+
+impl ValidationMessage {
+    pub fn is_error(&self) -> bool {
+        self.severity
+            .contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+    }
+}
+
+impl fmt::Display for ValidationMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = severity_level(self.severity)
+            .to_string()
+            .to_ascii_lowercase();
+        match &self.message_id_name {
+            Some(id_name) => write!(f, "{}: [{}] {}", level, id_name, self.message),
+            None => write!(f, "{}: {}", level, self.message),
+        }
+    }
+}
+
+/// Messages collected by the debug messenger callback, which may run on any thread.
+#[derive(Debug, Default)]
+pub struct ValidationSink {
+    messages: Mutex<Vec<ValidationMessage>>,
+    errors_only: bool,
+}
+
+impl ValidationSink {
+    /// Collects the messages `config` asks for: every reported one with `capture`, otherwise only
+    /// the errors `fail_on_error` needs, which are drained on every render.
+    pub(crate) fn new(config: &DebugMessengerConfig) -> Self {
+        Self {
+            messages: Mutex::default(),
+            errors_only: !config.capture,
+        }
+    }
+
+    fn push(&self, message: ValidationMessage) {
+        if self.errors_only && !message.is_error() {
+            return;
+        }
+        // Never panic inside the callback; a poisoned lock still holds valid messages.
+        let mut messages = self.messages.lock().unwrap_or_else(|err| err.into_inner());
+        messages.push(message);
+    }
+
+    /// Removes and returns every collected message.
+    pub fn take(&self) -> Vec<ValidationMessage> {
+        let mut messages = self.messages.lock().unwrap_or_else(|err| err.into_inner());
+        std::mem::take(&mut *messages)
+    }
+
+    /// Removes and returns the collected errors, leaving other messages in place.
+    pub fn take_errors(&self) -> Vec<ValidationMessage> {
+        let mut messages = self.messages.lock().unwrap_or_else(|err| err.into_inner());
+        let (errors, rest) = messages.drain(..).partition(ValidationMessage::is_error);
+        *messages = rest;
+        errors
+    }
+}
+
+/// Parses a minimum severity (`verbose`, `info`, `warning` or `error`) into that severity and
+/// every more severe one.
+pub fn parse_debug_severity(s: &str) -> Option<vk::DebugUtilsMessageSeverityFlagsEXT> {
+    use vk::DebugUtilsMessageSeverityFlagsEXT as Severity;
+
+    let error = Severity::ERROR;
+    let warning = error | Severity::WARNING;
+    let info = warning | Severity::INFO;
+    let verbose = info | Severity::VERBOSE;
+
+    match s.to_ascii_lowercase().as_str() {
+        "verbose" => Some(verbose),
+        "info" => Some(info),
+        "warning" | "warn" => Some(warning),
+        "error" => Some(error),
+        _ => None,
+    }
+}
+
+/// Parses a comma separated list of `general`, `validation` and `performance`.
+pub fn parse_debug_message_types(s: &str) -> Option<vk::DebugUtilsMessageTypeFlagsEXT> {
+    s.split(',')
+        .map(|name| match name.trim().to_ascii_lowercase().as_str() {
+            "general" => Some(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL),
+            "validation" => Some(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION),
+            "performance" => Some(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE),
+            _ => None,
+        })
+        .try_fold(vk::DebugUtilsMessageTypeFlagsEXT::empty(), |types, t| {
+            Some(types | t?)
+        })
+}
+
+/// Maps the most severe bit of `severity` to a log level.
+fn severity_level(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Level {
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        Level::Error
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        Level::Warn
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        Level::Info
+    } else {
+        Level::Debug
+    }
+}
+
+/// Picks the most specific target when a message has several types.
+fn message_target(message_types: vk::DebugUtilsMessageTypeFlagsEXT) -> &'static str {
+    if message_types.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        "vulkan::validation"
+    } else if message_types.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+        "vulkan::performance"
+    } else {
+        "vulkan::general"
+    }
+}
+
+unsafe fn c_str<'a>(ptr: *const c_char) -> Option<Cow<'a, str>> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy())
+    }
+}
+
+unsafe fn raw_slice<'a, T>(ptr: *const T, len: u32) -> &'a [T] {
+    if ptr.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len as usize)
+    }
+}
+
+/// Formats the message followed by the labels and objects it refers to.
+unsafe fn format_message(data: &vk::DebugUtilsMessengerCallbackDataEXT) -> String {
+    let mut text = String::new();
+    text.push_str(&c_str(data.p_message).unwrap_or_default());
+
+    let labels = [
+        (
+            "queue",
+            raw_slice(data.p_queue_labels, data.queue_label_count),
+        ),
+        (
+            "command buffer",
+            raw_slice(data.p_cmd_buf_labels, data.cmd_buf_label_count),
+        ),
+    ];
+    for (kind, labels) in labels.iter() {
+        for label in labels.iter() {
+            if let Some(name) = c_str(label.p_label_name) {
+                write!(text, "\n    {} label: {}", kind, name).unwrap();
+            }
+        }
+    }
+
+    for object in raw_slice(data.p_objects, data.object_count) {
+        write!(
+            text,
+            "\n    object: {:?} {:#x}",
+            object.object_type, object.object_handle
+        )
+        .unwrap();
+        if let Some(name) = c_str(object.p_object_name) {
+            write!(text, " \"{}\"", name).unwrap();
+        }
+    }
+
+    text
+}
+
+unsafe extern "system" fn vulkan_debug_utils_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    if p_callback_data.is_null() {
+        return vk::FALSE;
+    }
+    let data = &*p_callback_data;
+    let sink = (p_user_data as *const ValidationSink).as_ref();
+
+    let level = severity_level(message_severity);
+    let target = message_target(message_types);
+    let logged = log::log_enabled!(target: target, level);
+    if !logged && sink.is_none() {
+        return vk::FALSE;
+    }
+
+    let message_id_name = c_str(data.p_message_id_name).map(Cow::into_owned);
+    let message = format_message(data);
+
+    if logged {
+        match &message_id_name {
+            Some(id_name) => log::log!(target: target, level, "[{}] {}", id_name, message),
+            None => log::log!(target: target, level, "{}", message),
+        }
+    }
+    if let Some(sink) = sink {
+        sink.push(ValidationMessage {
+            severity: message_severity,
+            message_types,
+            message_id_name,
+            message_id_number: data.message_id_number,
+            message,
+        });
+    }
+
+    // Returning TRUE would abort the call that triggered the message.
+    vk::FALSE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> ValidationMessage {
+        ValidationMessage {
+            severity,
+            message_types: vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            message_id_name: None,
+            message_id_number: 0,
+            message: format!("{:?}", severity),
+        }
+    }
+
+    #[test]
+    fn fail_on_error_only_collects_errors() {
+        let config = DebugMessengerConfig {
+            fail_on_error: true,
+            ..DebugMessengerConfig::default()
+        };
+        let sink = ValidationSink::new(&config);
+        sink.push(message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING));
+        sink.push(message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR));
+        sink.push(message(vk::DebugUtilsMessageSeverityFlagsEXT::INFO));
+
+        assert_eq!(
+            sink.take(),
+            [message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)]
+        );
+    }
+
+    #[test]
+    fn capture_collects_every_message() {
+        let config = DebugMessengerConfig {
+            capture: true,
+            fail_on_error: true,
+            ..DebugMessengerConfig::default()
+        };
+        let sink = ValidationSink::new(&config);
+        sink.push(message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING));
+        sink.push(message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR));
+
+        assert_eq!(
+            sink.take_errors(),
+            [message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)]
+        );
+        assert_eq!(
+            sink.take(),
+            [message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING)]
+        );
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn parses_severities() {
+        use vk::DebugUtilsMessageSeverityFlagsEXT as Severity;
+
+        let error = Severity::ERROR;
+        let warning = error | Severity::WARNING;
+        let info = warning | Severity::INFO;
+        let verbose = info | Severity::VERBOSE;
+
+        assert_eq!(parse_debug_severity("error"), Some(error));
+        assert_eq!(parse_debug_severity("warning"), Some(warning));
+        assert_eq!(parse_debug_severity("WARN"), Some(warning));
+        assert_eq!(parse_debug_severity("info"), Some(info));
+        assert_eq!(parse_debug_severity("Verbose"), Some(verbose));
+        assert_eq!(parse_debug_severity("fatal"), None);
+        assert_eq!(parse_debug_severity(""), None);
+
+        let config = |severity| DebugMessengerConfig {
+            severity,
+            ..DebugMessengerConfig::default()
+        };
+        assert_eq!(config(warning).max_log_level(), LevelFilter::Warn);
+        assert_eq!(config(verbose).max_log_level(), LevelFilter::Debug);
+        assert_eq!(config(Severity::empty()).max_log_level(), LevelFilter::Off);
+    }
+
+    #[test]
+    fn parses_message_types() {
+        use vk::DebugUtilsMessageTypeFlagsEXT as Types;
+
+        assert_eq!(
+            parse_debug_message_types("validation"),
+            Some(Types::VALIDATION)
+        );
+        assert_eq!(
+            parse_debug_message_types("general, Performance"),
+            Some(Types::GENERAL | Types::PERFORMANCE)
+        );
+        assert_eq!(
+            parse_debug_message_types("validation,validation"),
+            Some(Types::VALIDATION)
+        );
+        assert_eq!(parse_debug_message_types("general,bogus"), None);
+        assert_eq!(parse_debug_message_types("general,"), None);
+        assert_eq!(parse_debug_message_types(""), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Depth attachment configuration and format negotiation.
+
+use ash::vk;
+
+use crate::error::{RenderError, Result};
+
+/// Depth-only formats in order of preference.
+pub const DEPTH_FORMATS: &[vk::Format] = &[
+    vk::Format::D32_SFLOAT,
+    vk::Format::X8_D24_UNORM_PACK32,
+    vk::Format::D16_UNORM,
+];
+
+/// Depth/stencil formats in order of preference.
+pub const DEPTH_STENCIL_FORMATS: &[vk::Format] = &[
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM_S8_UINT,
+];
+
+/// Depth formats by name, for [`parse_depth_format`].
+pub const SUPPORTED_DEPTH_FORMATS: &[(&str, vk::Format)] = &[
+    ("D32_SFLOAT", vk::Format::D32_SFLOAT),
+    ("X8_D24_UNORM_PACK32", vk::Format::X8_D24_UNORM_PACK32),
+    ("D16_UNORM", vk::Format::D16_UNORM),
+    ("D32_SFLOAT_S8_UINT", vk::Format::D32_SFLOAT_S8_UINT),
+    ("D24_UNORM_S8_UINT", vk::Format::D24_UNORM_S8_UINT),
+    ("D16_UNORM_S8_UINT", vk::Format::D16_UNORM_S8_UINT),
+];
+
+/// Settings for the optional depth attachment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthConfig {
+    /// Candidate formats in order of preference; the first one the device supports is used.
+    pub formats: Vec<vk::Format>,
+    /// Value the depth attachment is cleared to. The pipeline keeps fragments with a depth less
+    /// than or equal to the stored one.
+    pub clear_depth: f32,
+    /// Copy the depth attachment to host memory after every render, see
+    /// [`OffscreenRenderer::depth_image`](crate::OffscreenRenderer::depth_image).
+    pub readback: bool,
+}
+
+impl Default for DepthConfig {
+    fn default() -> Self {
+        Self {
+            formats: DEPTH_FORMATS.to_vec(),
+            clear_depth: 1.0,
+            readback: false,
+        }
+    }
+}
+
+impl DepthConfig {
+    /// A depth/stencil attachment; the stencil aspect is cleared to 0.
+    pub fn depth_stencil() -> Self {
+        Self {
+            formats: DEPTH_STENCIL_FORMATS.to_vec(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Looks up a depth format by its Vulkan name without the `VK_FORMAT_` prefix, ignoring case.
+pub fn parse_depth_format(name: &str) -> Option<vk::Format> {
+    SUPPORTED_DEPTH_FORMATS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, format)| format)
+}
+
+/// Picks the first of `config.formats` that `physical_device` supports as an optimal-tiling depth
+/// attachment, and as a transfer source if it is read back.
+pub(crate) fn select_depth_format(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    config: &DepthConfig,
+) -> Result<vk::Format> {
+    let mut required = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT;
+    if config.readback {
+        required |= vk::FormatFeatureFlags::TRANSFER_SRC;
+    }
+
+    config
+        .formats
+        .iter()
+        .copied()
+        .find(|&format| {
+            let properties =
+                unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+            properties.optimal_tiling_features.contains(required)
+        })
+        .ok_or_else(|| RenderError::UnsupportedDepthFormats(config.formats.clone()))
+}
+
+pub(crate) fn has_stencil(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+            | vk::Format::S8_UINT
+    )
+}
+
+/// Aspects of a depth format to create the attachment view with.
+pub(crate) fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    if has_stencil(format) {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else {
+        vk::ImageAspectFlags::DEPTH
+    }
+}
+
+/// Bytes per texel when only the depth aspect of `format` is copied to a buffer.
+pub(crate) fn depth_copy_size(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::D16_UNORM_S8_UINT => 2,
+        _ => 4,
+    }
+}
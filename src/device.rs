@@ -0,0 +1,510 @@
+//! Physical device discovery and selection.
+
+use std::{env, ffi::CStr, fmt};
+
+use ash::{prelude::VkResult, vk};
+
+use crate::features::{DeviceRequirements, EnabledCapabilities, FeatureSet, MissingCapabilities};
+
+/// Environment variable consulted when no [`DeviceSelector`] is configured explicitly.
+///
+/// Accepts the same syntax as [`parse_device_selector`].
+pub const DEVICE_ENV_VAR: &str = "OFFSCREEN_DEVICE";
+
+/// Lowest device API version the renderer can use, matching the `spirv-unknown-vulkan1.1`
+/// shader target.
+pub const REQUIRED_API_VERSION: u32 = vk::API_VERSION_1_1;
+
+/// Which physical device to render on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Position in the order reported by `vkEnumeratePhysicalDevices`.
+    Index(usize),
+    /// Case-insensitive substring of the device name.
+    Name(String),
+    /// `VkPhysicalDeviceIDProperties::deviceUUID`.
+    Uuid([u8; vk::UUID_SIZE]),
+}
+
+impl DeviceSelector {
+    /// Reads [`DEVICE_ENV_VAR`], ignoring it when unset or empty.
+    pub fn from_env() -> Option<Self> {
+        env::var(DEVICE_ENV_VAR)
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| parse_device_selector(s.trim()))
+    }
+
+    fn matches(&self, info: &PhysicalDeviceInfo) -> bool {
+        match self {
+            DeviceSelector::Index(index) => info.index == *index,
+            DeviceSelector::Name(name) => info.name.to_lowercase().contains(&name.to_lowercase()),
+            DeviceSelector::Uuid(uuid) => info.uuid == *uuid,
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceSelector::Index(index) => write!(f, "index {}", index),
+            DeviceSelector::Name(name) => write!(f, "name containing '{}'", name),
+            DeviceSelector::Uuid(uuid) => write!(f, "UUID {}", format_uuid(uuid)),
+        }
+    }
+}
+
+/// Parses a device index or a UUID, and otherwise falls back to matching by name.
+pub fn parse_device_selector(s: &str) -> DeviceSelector {
+    if let Ok(index) = s.parse() {
+        return DeviceSelector::Index(index);
+    }
+    match parse_uuid(s) {
+        Some(uuid) => DeviceSelector::Uuid(uuid),
+        None => DeviceSelector::Name(s.to_string()),
+    }
+}
+
+/// Parses 32 hex digits, optionally separated by dashes.
+fn parse_uuid(s: &str) -> Option<[u8; vk::UUID_SIZE]> {
+    let digits: Vec<u8> = s
+        .chars()
+        .filter(|&c| c != '-')
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+
+    if digits.len() != 2 * vk::UUID_SIZE {
+        return None;
+    }
+
+    let mut uuid = [0; vk::UUID_SIZE];
+    for (byte, pair) in uuid.iter_mut().zip(digits.chunks(2)) {
+        *byte = pair[0] << 4 | pair[1];
+    }
+    Some(uuid)
+}
+
+pub fn format_uuid(uuid: &[u8; vk::UUID_SIZE]) -> String {
+    let hex: Vec<String> = uuid.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..].concat()
+    )
+}
+
+/// Formats a packed `VK_MAKE_API_VERSION` value as `major.minor.patch`.
+pub fn format_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+/// Properties of one physical device, as needed for selection and reporting.
+#[derive(Debug, Clone)]
+pub struct PhysicalDeviceInfo {
+    pub handle: vk::PhysicalDevice,
+    /// Position in the order reported by `vkEnumeratePhysicalDevices`.
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: u32,
+    /// Vendor-specific encoding; see [`PhysicalDeviceInfo::driver_info`] for a readable form.
+    pub driver_version: u32,
+    pub driver_name: String,
+    pub driver_info: String,
+    pub uuid: [u8; vk::UUID_SIZE],
+    pub memory_heaps: Vec<vk::MemoryHeap>,
+    /// First queue family supporting graphics, if any.
+    pub graphics_queue_family_index: Option<u32>,
+    pub features: FeatureSet,
+    /// Supported device extensions with their spec versions.
+    pub extensions: Vec<(String, u32)>,
+}
+
+impl PhysicalDeviceInfo {
+    pub fn query(
+        instance: &ash::Instance,
+        index: usize,
+        handle: vk::PhysicalDevice,
+    ) -> VkResult<Self> {
+        let properties = unsafe { instance.get_physical_device_properties(handle) };
+
+        let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+        let mut driver_properties = vk::PhysicalDeviceDriverProperties::default();
+        if properties.api_version >= vk::API_VERSION_1_1 {
+            let mut properties2 =
+                vk::PhysicalDeviceProperties2::builder().push_next(&mut id_properties);
+            if properties.api_version >= vk::API_VERSION_1_2 {
+                properties2 = properties2.push_next(&mut driver_properties);
+            }
+            unsafe { instance.get_physical_device_properties2(handle, &mut properties2) };
+        }
+
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(handle) };
+
+        let graphics_queue_family_index =
+            unsafe { instance.get_physical_device_queue_family_properties(handle) }
+                .into_iter()
+                .position(|queue_family| {
+                    queue_family.queue_count > 0
+                        && queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+                })
+                .map(|i| i as u32);
+
+        let extensions: Vec<(String, u32)> =
+            unsafe { instance.enumerate_device_extension_properties(handle) }?
+                .iter()
+                .map(|extension| {
+                    (
+                        c_chars_to_string(&extension.extension_name),
+                        extension.spec_version,
+                    )
+                })
+                .collect();
+        let features = FeatureSet::query(instance, handle, properties.api_version, &extensions);
+
+        Ok(Self {
+            handle,
+            index,
+            name: c_chars_to_string(&properties.device_name),
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            api_version: properties.api_version,
+            driver_version: properties.driver_version,
+            driver_name: c_chars_to_string(&driver_properties.driver_name),
+            driver_info: c_chars_to_string(&driver_properties.driver_info),
+            uuid: id_properties.device_uuid,
+            memory_heaps: memory_properties.memory_heaps
+                [..memory_properties.memory_heap_count as usize]
+                .to_vec(),
+            graphics_queue_family_index,
+            features,
+            extensions,
+        })
+    }
+
+    /// Default preference: discrete over integrated over virtual over CPU, then larger
+    /// device-local memory.
+    pub fn score(&self) -> (u32, u64) {
+        let type_score = match self.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 1,
+            _ => 0,
+        };
+
+        (type_score, self.device_local_memory())
+    }
+
+    /// Total size of all device-local heaps in bytes.
+    pub fn device_local_memory(&self) -> u64 {
+        self.memory_heaps
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum()
+    }
+
+    /// Returns the graphics queue family and capabilities to use, or why the renderer cannot
+    /// use this device.
+    fn check_requirements(
+        &self,
+        requirements: &DeviceRequirements,
+    ) -> Result<(u32, EnabledCapabilities), RejectionReason> {
+        if self.api_version < REQUIRED_API_VERSION {
+            return Err(RejectionReason::ApiVersionTooLow(self.api_version));
+        }
+        let queue_family_index = self
+            .graphics_queue_family_index
+            .ok_or(RejectionReason::NoGraphicsQueue)?;
+        let capabilities = requirements
+            .negotiate(self.features, &self.extensions, self.api_version)
+            .map_err(RejectionReason::MissingCapabilities)?;
+
+        Ok((queue_family_index, capabilities))
+    }
+}
+
+pub(crate) fn c_chars_to_string(chars: &[std::os::raw::c_char]) -> String {
+    unsafe { CStr::from_ptr(chars.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn enumerate_physical_devices(instance: &ash::Instance) -> VkResult<Vec<PhysicalDeviceInfo>> {
+    unsafe { instance.enumerate_physical_devices() }?
+        .into_iter()
+        .enumerate()
+        .map(|(index, handle)| PhysicalDeviceInfo::query(instance, index, handle))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    /// The device does not match the configured [`DeviceSelector`].
+    NotSelected,
+    ApiVersionTooLow(u32),
+    NoGraphicsQueue,
+    MissingCapabilities(MissingCapabilities),
+    /// Usable, but another device scored higher.
+    LowerScore,
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RejectionReason::NotSelected => write!(f, "does not match the device selector"),
+            RejectionReason::ApiVersionTooLow(version) => write!(
+                f,
+                "supports Vulkan {} but {} is required",
+                format_version(*version),
+                format_version(REQUIRED_API_VERSION)
+            ),
+            RejectionReason::NoGraphicsQueue => write!(f, "has no graphics queue"),
+            RejectionReason::MissingCapabilities(missing) => write!(f, "{}", missing),
+            RejectionReason::LowerScore => write!(f, "a preferred device is available"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RejectedDevice {
+    pub index: usize,
+    pub name: String,
+    pub reason: RejectionReason,
+}
+
+impl fmt::Display for RejectedDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.index, self.name, self.reason)
+    }
+}
+
+/// Outcome of [`select_physical_device`].
+#[derive(Debug, Clone)]
+pub struct DeviceSelection {
+    pub device: PhysicalDeviceInfo,
+    /// Graphics queue family of `device`.
+    pub queue_family_index: u32,
+    /// Negotiated from the [`DeviceRequirements`] passed to [`select_physical_device`].
+    pub capabilities: EnabledCapabilities,
+    /// Every other enumerated device and why it was not chosen.
+    pub rejected: Vec<RejectedDevice>,
+}
+
+/// Picks the highest scoring device that matches `selector`, meets the renderer's requirements
+/// and supports everything required by `requirements`.
+///
+/// On failure the reason every device was rejected is returned instead.
+pub fn select_physical_device(
+    devices: Vec<PhysicalDeviceInfo>,
+    selector: Option<&DeviceSelector>,
+    requirements: &DeviceRequirements,
+) -> Result<DeviceSelection, Vec<RejectedDevice>> {
+    let mut rejected = Vec::new();
+    let mut candidates = Vec::new();
+
+    for device in devices {
+        let outcome = match selector {
+            Some(selector) if !selector.matches(&device) => Err(RejectionReason::NotSelected),
+            _ => device.check_requirements(requirements),
+        };
+
+        match outcome {
+            Ok((queue_family_index, capabilities)) => {
+                candidates.push((device, queue_family_index, capabilities))
+            }
+            Err(reason) => rejected.push(RejectedDevice {
+                index: device.index,
+                name: device.name,
+                reason,
+            }),
+        }
+    }
+
+    // Stable sort keeps enumeration order among equally scored devices.
+    candidates.sort_by_key(|(device, _, _)| std::cmp::Reverse(device.score()));
+
+    let mut candidates = candidates.into_iter();
+    let (device, queue_family_index, capabilities) = match candidates.next() {
+        Some(candidate) => candidate,
+        None => return Err(rejected),
+    };
+
+    rejected.extend(candidates.map(|(device, _, _)| RejectedDevice {
+        index: device.index,
+        name: device.name,
+        reason: RejectionReason::LowerScore,
+    }));
+    rejected.sort_by_key(|device| device.index);
+
+    Ok(DeviceSelection {
+        device,
+        queue_family_index,
+        capabilities,
+        rejected,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::Feature;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    /// A Vulkan 1.2 device with a graphics queue, the memory model and one device-local heap.
+    fn device(
+        index: usize,
+        name: &str,
+        device_type: vk::PhysicalDeviceType,
+        device_local_memory: u64,
+    ) -> PhysicalDeviceInfo {
+        let mut features = FeatureSet::default();
+        features.insert(Feature::VulkanMemoryModel);
+
+        PhysicalDeviceInfo {
+            handle: vk::PhysicalDevice::null(),
+            index,
+            name: name.to_string(),
+            device_type,
+            vendor_id: 0,
+            device_id: 0,
+            api_version: vk::API_VERSION_1_2,
+            driver_version: 0,
+            driver_name: String::new(),
+            driver_info: String::new(),
+            uuid: [index as u8; vk::UUID_SIZE],
+            memory_heaps: vec![vk::MemoryHeap {
+                size: device_local_memory,
+                flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+            }],
+            graphics_queue_family_index: Some(0),
+            features,
+            extensions: Vec::new(),
+        }
+    }
+
+    fn select(
+        devices: Vec<PhysicalDeviceInfo>,
+        selector: Option<&DeviceSelector>,
+    ) -> Option<usize> {
+        select_physical_device(devices, selector, &DeviceRequirements::default())
+            .ok()
+            .map(|selection| selection.device.index)
+    }
+
+    #[test]
+    fn parses_selectors() {
+        assert_eq!(parse_device_selector("1"), DeviceSelector::Index(1));
+        assert_eq!(
+            parse_device_selector("GeForce"),
+            DeviceSelector::Name("GeForce".to_string())
+        );
+
+        let mut uuid = [0; vk::UUID_SIZE];
+        uuid[0] = 0x01;
+        uuid[15] = 0xef;
+        assert_eq!(
+            parse_device_selector("01000000-0000-0000-0000-0000000000ef"),
+            DeviceSelector::Uuid(uuid)
+        );
+        assert_eq!(
+            parse_device_selector("010000000000000000000000000000EF"),
+            DeviceSelector::Uuid(uuid)
+        );
+        assert_eq!(format_uuid(&uuid), "01000000-0000-0000-0000-0000000000ef");
+        // One digit short is a name.
+        assert_eq!(
+            parse_device_selector("01000000000000000000000000000ef"),
+            DeviceSelector::Name("01000000000000000000000000000ef".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_the_environment() {
+        env::set_var(DEVICE_ENV_VAR, " llvmpipe ");
+        assert_eq!(
+            DeviceSelector::from_env(),
+            Some(DeviceSelector::Name("llvmpipe".to_string()))
+        );
+        env::set_var(DEVICE_ENV_VAR, "2");
+        assert_eq!(DeviceSelector::from_env(), Some(DeviceSelector::Index(2)));
+        env::set_var(DEVICE_ENV_VAR, " ");
+        assert_eq!(DeviceSelector::from_env(), None);
+        env::remove_var(DEVICE_ENV_VAR);
+        assert_eq!(DeviceSelector::from_env(), None);
+    }
+
+    #[test]
+    fn scores_by_type_then_memory() {
+        let cpu = device(0, "llvmpipe", vk::PhysicalDeviceType::CPU, 64 * GIB);
+        let integrated = device(1, "Intel", vk::PhysicalDeviceType::INTEGRATED_GPU, GIB);
+        let discrete = device(2, "Radeon", vk::PhysicalDeviceType::DISCRETE_GPU, 4 * GIB);
+        let larger = device(3, "GeForce", vk::PhysicalDeviceType::DISCRETE_GPU, 8 * GIB);
+
+        assert!(discrete.score() > integrated.score());
+        assert!(integrated.score() > cpu.score());
+        assert!(larger.score() > discrete.score());
+
+        let devices = vec![cpu, integrated, discrete, larger];
+        assert_eq!(select(devices.clone(), None), Some(3));
+        assert_eq!(select(devices[..3].to_vec(), None), Some(2));
+        assert_eq!(select(devices[..2].to_vec(), None), Some(1));
+    }
+
+    #[test]
+    fn selects_by_index_name_or_uuid() {
+        let devices = vec![
+            device(
+                0,
+                "llvmpipe (LLVM 12.0.0)",
+                vk::PhysicalDeviceType::CPU,
+                GIB,
+            ),
+            device(
+                1,
+                "NVIDIA GeForce RTX",
+                vk::PhysicalDeviceType::DISCRETE_GPU,
+                GIB,
+            ),
+        ];
+
+        assert_eq!(
+            select(devices.clone(), Some(&DeviceSelector::Index(0))),
+            Some(0)
+        );
+        assert_eq!(
+            select(devices.clone(), Some(&parse_device_selector("LLVM"))),
+            Some(0)
+        );
+        assert_eq!(
+            select(
+                devices.clone(),
+                Some(&DeviceSelector::Uuid([0; vk::UUID_SIZE]))
+            ),
+            Some(0)
+        );
+
+        let rejected = select_physical_device(
+            devices,
+            Some(&DeviceSelector::Index(2)),
+            &DeviceRequirements::default(),
+        )
+        .unwrap_err();
+        assert!(rejected
+            .iter()
+            .all(|device| device.reason == RejectionReason::NotSelected));
+    }
+}
@@ -0,0 +1,169 @@
+use std::{error::Error, ffi::CString, fmt, io, path::PathBuf};
+
+use ash::vk;
+
+use crate::{debug::ValidationMessage, device::RejectedDevice};
+
+pub type Result<T> = std::result::Result<T, RenderError>;
+
+/// Everything that can go wrong while setting up the renderer, rendering or writing output.
+#[derive(Debug)]
+pub enum RenderError {
+    /// The Vulkan loader library could not be loaded.
+    Loading(ash::LoadingError),
+    /// Instance-level entry points could not be loaded.
+    InstanceLoad(Vec<&'static str>),
+    /// A requested instance layer is not installed.
+    LayerNotPresent(CString),
+    /// The requested [`RenderConfig`](crate::RenderConfig) cannot be rendered.
+    InvalidConfig(String),
+    /// The color format is not supported as a color attachment or by the readback path.
+    UnsupportedFormat(vk::Format),
+    /// None of the candidate depth formats is supported as a depth attachment, or for readback if
+    /// requested.
+    UnsupportedDepthFormats(Vec<vk::Format>),
+    /// Every physical device was rejected, for the given reasons.
+    NoSuitableDevice(Vec<RejectedDevice>),
+    /// No memory type satisfies both the resource requirements and the requested properties.
+    NoMemoryType {
+        type_bits: u32,
+        properties: vk::MemoryPropertyFlags,
+    },
+    /// Every matching memory type is in a heap without enough budget left for `size` bytes.
+    MemoryBudgetExceeded {
+        size: vk::DeviceSize,
+    },
+    /// A model file could not be read or parsed.
+    Model {
+        path: PathBuf,
+        message: String,
+    },
+    /// The SPIR-V module was rejected by the driver.
+    ShaderModule(vk::Result),
+    /// The validation layer reported errors while
+    /// [`DebugMessengerConfig::fail_on_error`](crate::DebugMessengerConfig::fail_on_error) was set.
+    Validation(Vec<ValidationMessage>),
+    /// The logical device was lost; the renderer has to be recreated.
+    DeviceLost,
+    /// Any other failing Vulkan call.
+    Vulkan(vk::Result),
+    Io(io::Error),
+    Encode(png::EncodingError),
+    /// An encoder from the `image` crate failed.
+    ImageEncode(::image::ImageError),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::Loading(err) => write!(f, "failed to load Vulkan: {}", err),
+            RenderError::InstanceLoad(names) => {
+                write!(f, "failed to load instance functions: {}", names.join(", "))
+            }
+            RenderError::LayerNotPresent(name) => {
+                write!(f, "instance layer {:?} is not available", name)
+            }
+            RenderError::InvalidConfig(message) => write!(f, "invalid config: {}", message),
+            RenderError::UnsupportedFormat(format) => {
+                write!(f, "format {:?} is not supported", format)
+            }
+            RenderError::UnsupportedDepthFormats(formats) => {
+                write!(f, "none of the depth formats {:?} is supported", formats)
+            }
+            RenderError::NoSuitableDevice(rejected) => {
+                write!(f, "no suitable physical device found")?;
+                if rejected.is_empty() {
+                    write!(f, " (no devices enumerated)")?;
+                }
+                for device in rejected {
+                    write!(f, "\n  {}", device)?;
+                }
+                Ok(())
+            }
+            RenderError::NoMemoryType {
+                type_bits,
+                properties,
+            } => write!(
+                f,
+                "no memory type in {:#b} with properties {:?}",
+                type_bits, properties
+            ),
+            RenderError::MemoryBudgetExceeded { size } => {
+                write!(f, "no memory heap has {} bytes left in its budget", size)
+            }
+            RenderError::Model { path, message } => {
+                write!(f, "failed to load model {}: {}", path.display(), message)
+            }
+            RenderError::ShaderModule(result) => {
+                write!(f, "failed to create shader module: {}", result)
+            }
+            RenderError::Validation(messages) => {
+                write!(f, "validation reported {} error(s)", messages.len())?;
+                for message in messages {
+                    write!(f, "\n  {}", message)?;
+                }
+                Ok(())
+            }
+            RenderError::DeviceLost => write!(f, "device lost"),
+            RenderError::Vulkan(result) => write!(f, "Vulkan error: {}", result),
+            RenderError::Io(err) => write!(f, "I/O error: {}", err),
+            RenderError::Encode(err) => write!(f, "failed to encode image: {}", err),
+            RenderError::ImageEncode(err) => write!(f, "failed to encode image: {}", err),
+        }
+    }
+}
+
+impl Error for RenderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RenderError::Loading(err) => Some(err),
+            RenderError::ShaderModule(result) | RenderError::Vulkan(result) => Some(result),
+            RenderError::Io(err) => Some(err),
+            RenderError::Encode(err) => Some(err),
+            RenderError::ImageEncode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<vk::Result> for RenderError {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_DEVICE_LOST => RenderError::DeviceLost,
+            result => RenderError::Vulkan(result),
+        }
+    }
+}
+
+impl From<ash::LoadingError> for RenderError {
+    fn from(err: ash::LoadingError) -> Self {
+        RenderError::Loading(err)
+    }
+}
+
+impl From<ash::InstanceError> for RenderError {
+    fn from(err: ash::InstanceError) -> Self {
+        match err {
+            ash::InstanceError::LoadError(names) => RenderError::InstanceLoad(names),
+            ash::InstanceError::VkError(result) => result.into(),
+        }
+    }
+}
+
+impl From<io::Error> for RenderError {
+    fn from(err: io::Error) -> Self {
+        RenderError::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for RenderError {
+    fn from(err: png::EncodingError) -> Self {
+        RenderError::Encode(err)
+    }
+}
+
+impl From<::image::ImageError> for RenderError {
+    fn from(err: ::image::ImageError) -> Self {
+        RenderError::ImageEncode(err)
+    }
+}
@@ -0,0 +1,299 @@
+//! Minimal OpenEXR writer.
+//!
+//! Writes a single-part scanline image with uncompressed 32-bit float channels, which every EXR
+//! reader supports and which holds half and float renders without loss.
+
+use std::io::{self, Write};
+
+const MAGIC: u32 = 20000630;
+/// File format version 2 with no flags: single-part scanline image with short names.
+const VERSION: u32 = 2;
+
+const NO_COMPRESSION: u8 = 0;
+const INCREASING_Y: u8 = 0;
+const PIXEL_TYPE_FLOAT: u32 = 2;
+
+/// Channel names for RGBA pixels.
+pub const RGBA: &[&str] = &["R", "G", "B", "A"];
+/// Channel name for depth values.
+pub const DEPTH: &[&str] = &["Z"];
+
+/// Writes tightly packed `pixels`, with the values of each pixel in the order of `channels`, as an
+/// uncompressed EXR file.
+pub fn encode<W: Write>(
+    mut writer: W,
+    channels: &[&str],
+    pixels: &[f32],
+    width: u32,
+    height: u32,
+) -> io::Result<()> {
+    if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid EXR size {}x{}", width, height),
+        ));
+    }
+    let channel_count = channels.len();
+    assert_eq!(
+        pixels.len(),
+        channel_count * width as usize * height as usize
+    );
+
+    // EXR stores channels sorted by name; keep each one's index within a pixel.
+    let mut sorted_channels: Vec<(&str, usize)> = channels
+        .iter()
+        .enumerate()
+        .map(|(i, &name)| (name, i))
+        .collect();
+    sorted_channels.sort_unstable();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+
+    let mut channel_list = Vec::new();
+    for (name, _) in &sorted_channels {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and three reserved bytes, then x and y sampling.
+        channel_list.extend_from_slice(&[0; 4]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channel_list);
+
+    write_attribute(&mut header, "compression", "compression", &[NO_COMPRESSION]);
+
+    let mut window = Vec::new();
+    for value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[INCREASING_Y]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+
+    let mut center = Vec::new();
+    center.extend_from_slice(&0f32.to_le_bytes());
+    center.extend_from_slice(&0f32.to_le_bytes());
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &center);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+
+    header.push(0);
+
+    // Uncompressed files store one scanline per chunk, each preceded by its y and byte count.
+    let line_size = channel_count * 4 * width as usize;
+    let chunk_size = 8 + line_size;
+    let first_chunk = header.len() + 8 * height as usize;
+
+    writer.write_all(&header)?;
+    for y in 0..height as usize {
+        let offset = (first_chunk + y * chunk_size) as u64;
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(chunk_size);
+    for (y, row) in pixels
+        .chunks_exact(channel_count * width as usize)
+        .enumerate()
+    {
+        line.clear();
+        line.extend_from_slice(&(y as i32).to_le_bytes());
+        line.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for &(_, component) in &sorted_channels {
+            for pixel in row.chunks_exact(channel_count) {
+                line.extend_from_slice(&pixel[component].to_le_bytes());
+            }
+        }
+        writer.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, type_name: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(type_name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    /// Reads attributes and values off the front of an EXR file.
+    struct Parser<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Parser<'a> {
+        fn bytes(&mut self, len: usize) -> &'a [u8] {
+            let (bytes, rest) = self.data.split_at(len);
+            self.data = rest;
+            bytes
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_le_bytes(self.bytes(4).try_into().unwrap())
+        }
+
+        fn i32(&mut self) -> i32 {
+            i32::from_le_bytes(self.bytes(4).try_into().unwrap())
+        }
+
+        fn u64(&mut self) -> u64 {
+            u64::from_le_bytes(self.bytes(8).try_into().unwrap())
+        }
+
+        fn string(&mut self) -> &'a str {
+            let end = self.data.iter().position(|&byte| byte == 0).unwrap();
+            let string = std::str::from_utf8(&self.data[..end]).unwrap();
+            self.bytes(end + 1);
+            string
+        }
+
+        /// The next `count` floats as bits, which compare exactly.
+        fn float_bits(&mut self, count: usize) -> Vec<u32> {
+            (0..count).map(|_| self.u32()).collect()
+        }
+
+        /// The next attribute as `(name, type, value)`, or `None` at the end of the header.
+        fn attribute(&mut self) -> Option<(&'a str, &'a str, &'a [u8])> {
+            let name = self.string();
+            if name.is_empty() {
+                return None;
+            }
+            let type_name = self.string();
+            let size = self.i32() as usize;
+            Some((name, type_name, self.bytes(size)))
+        }
+    }
+
+    fn bits(values: &[f32]) -> Vec<u32> {
+        values.iter().map(|value| value.to_bits()).collect()
+    }
+
+    #[test]
+    fn header_offsets_and_channel_order() {
+        // Two rows of two pixels, with each channel's values distinct.
+        let pixels: Vec<f32> = (0..2 * 2 * 4).map(|i| i as f32).collect();
+        let mut file = Vec::new();
+        encode(&mut file, RGBA, &pixels, 2, 2).unwrap();
+
+        let mut parser = Parser { data: &file };
+        assert_eq!(parser.u32(), MAGIC);
+        assert_eq!(parser.u32(), VERSION);
+
+        let mut attributes = Vec::new();
+        while let Some(attribute) = parser.attribute() {
+            attributes.push(attribute);
+        }
+        let names: Vec<(&str, &str)> = attributes
+            .iter()
+            .map(|&(name, type_name, _)| (name, type_name))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("channels", "chlist"),
+                ("compression", "compression"),
+                ("dataWindow", "box2i"),
+                ("displayWindow", "box2i"),
+                ("lineOrder", "lineOrder"),
+                ("pixelAspectRatio", "float"),
+                ("screenWindowCenter", "v2f"),
+                ("screenWindowWidth", "float"),
+            ]
+        );
+
+        // Channels are sorted by name, each a full-resolution float channel.
+        let mut channels = Parser {
+            data: attributes[0].2,
+        };
+        for &name in &["A", "B", "G", "R"] {
+            assert_eq!(channels.string(), name);
+            assert_eq!(channels.u32(), PIXEL_TYPE_FLOAT);
+            assert_eq!(channels.bytes(4), [0; 4]);
+            assert_eq!((channels.i32(), channels.i32()), (1, 1));
+        }
+        assert_eq!(channels.data, [0]);
+
+        assert_eq!(attributes[1].2, [NO_COMPRESSION]);
+        let mut window = Parser {
+            data: attributes[2].2,
+        };
+        let window: Vec<i32> = (0..4).map(|_| window.i32()).collect();
+        assert_eq!(window, [0, 0, 1, 1]);
+        assert_eq!(attributes[3].2, attributes[2].2);
+        assert_eq!(attributes[4].2, [INCREASING_Y]);
+
+        // The offset table points at each scanline chunk.
+        let line_size = 4 * 4 * 2;
+        let first_chunk = file.len() - parser.data.len() + 8 * 2;
+        let offsets = [parser.u64(), parser.u64()];
+        assert_eq!(
+            offsets,
+            [first_chunk as u64, (first_chunk + 8 + line_size) as u64]
+        );
+        assert_eq!(file.len(), first_chunk + 2 * (8 + line_size));
+
+        for (y, &offset) in offsets.iter().enumerate() {
+            let mut chunk = Parser {
+                data: &file[offset as usize..],
+            };
+            assert_eq!(chunk.i32(), y as i32);
+            assert_eq!(chunk.i32(), line_size as i32);
+
+            // All A values of the line, then B, G and R.
+            let row = &pixels[y * 8..];
+            assert_eq!(
+                chunk.float_bits(8),
+                bits(&[row[3], row[7], row[2], row[6], row[1], row[5], row[0], row[4]])
+            );
+        }
+    }
+
+    #[test]
+    fn depth_channel() {
+        let mut file = Vec::new();
+        encode(&mut file, DEPTH, &[0.25, 0.5, 0.75], 3, 1).unwrap();
+
+        let mut parser = Parser { data: &file[8..] };
+        let (name, _, channels) = parser.attribute().unwrap();
+        assert_eq!(name, "channels");
+        assert_eq!(&channels[..2], b"Z\0");
+        while parser.attribute().is_some() {}
+
+        let offset = parser.u64() as usize;
+        let mut chunk = Parser {
+            data: &file[offset..],
+        };
+        assert_eq!((chunk.i32(), chunk.i32()), (0, 12));
+        assert_eq!(chunk.float_bits(3), bits(&[0.25, 0.5, 0.75]));
+        assert!(chunk.data.is_empty());
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        assert!(encode(Vec::new(), RGBA, &[], 0, 1).is_err());
+        assert!(encode(Vec::new(), DEPTH, &[], 1, 0).is_err());
+    }
+}
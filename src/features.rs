@@ -0,0 +1,425 @@
+//! Device feature and extension negotiation.
+
+use std::{ffi::CString, fmt};
+
+use ash::vk;
+
+use crate::memory::MEMORY_BUDGET_EXTENSION;
+
+pub const VULKAN_MEMORY_MODEL_EXTENSION: &str = "VK_KHR_vulkan_memory_model";
+
+macro_rules! features {
+    ($($variant:ident => $group:ident . $field:ident,)*) => {
+        /// A device feature the renderer knows how to query and enable.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Feature {
+            $($variant,)*
+        }
+
+        impl Feature {
+            pub const ALL: &'static [Feature] = &[$(Feature::$variant,)*];
+
+            /// Field name in the Vulkan feature struct, e.g. `sample_rate_shading`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Feature::$variant => stringify!($field),)*
+                }
+            }
+        }
+
+        impl FeatureSet {
+            fn from_vk(vk_features: &VkFeatures) -> Self {
+                let mut set = Self::default();
+                $(
+                    if vk_features.$group.$field == vk::TRUE {
+                        set.insert(Feature::$variant);
+                    }
+                )*
+                set
+            }
+
+            fn to_vk(self) -> VkFeatures {
+                let mut vk_features = VkFeatures::default();
+                $(
+                    if self.contains(Feature::$variant) {
+                        vk_features.$group.$field = vk::TRUE;
+                    }
+                )*
+                vk_features
+            }
+        }
+    };
+}
+
+features! {
+    RobustBufferAccess => core.robust_buffer_access,
+    IndependentBlend => core.independent_blend,
+    GeometryShader => core.geometry_shader,
+    TessellationShader => core.tessellation_shader,
+    SampleRateShading => core.sample_rate_shading,
+    DepthClamp => core.depth_clamp,
+    DepthBiasClamp => core.depth_bias_clamp,
+    FillModeNonSolid => core.fill_mode_non_solid,
+    DepthBounds => core.depth_bounds,
+    WideLines => core.wide_lines,
+    MultiDrawIndirect => core.multi_draw_indirect,
+    SamplerAnisotropy => core.sampler_anisotropy,
+    ShaderFloat64 => core.shader_float64,
+    ShaderInt64 => core.shader_int64,
+    ShaderInt16 => core.shader_int16,
+    VulkanMemoryModel => memory_model.vulkan_memory_model,
+    VulkanMemoryModelDeviceScope => memory_model.vulkan_memory_model_device_scope,
+}
+
+/// The Vulkan structs [`Feature`]s map to, split by where they are chained.
+#[derive(Default)]
+struct VkFeatures {
+    core: vk::PhysicalDeviceFeatures,
+    memory_model: vk::PhysicalDeviceVulkanMemoryModelFeatures,
+}
+
+impl Feature {
+    fn is_memory_model(self) -> bool {
+        matches!(
+            self,
+            Feature::VulkanMemoryModel | Feature::VulkanMemoryModelDeviceScope
+        )
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Set of [`Feature`]s, stored as a bit mask so it stays `Copy` and `Send`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeatureSet(u64);
+
+impl FeatureSet {
+    pub fn contains(self, feature: Feature) -> bool {
+        self.0 & (1 << feature as u64) != 0
+    }
+
+    pub fn insert(&mut self, feature: Feature) {
+        self.0 |= 1 << feature as u64;
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Feature> {
+        Feature::ALL
+            .iter()
+            .copied()
+            .filter(move |&feature| self.contains(feature))
+    }
+
+    /// Queries what `physical_device` supports with `vkGetPhysicalDeviceFeatures2`.
+    ///
+    /// `extensions` are the device's supported extensions; memory model features are only
+    /// queried when the device is Vulkan 1.2 or exposes `VK_KHR_vulkan_memory_model`.
+    pub fn query(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        api_version: u32,
+        extensions: &[(String, u32)],
+    ) -> Self {
+        let mut vk_features = VkFeatures::default();
+
+        vk_features.core = if api_version >= vk::API_VERSION_1_1 {
+            let has_memory_model = api_version >= vk::API_VERSION_1_2
+                || extensions
+                    .iter()
+                    .any(|(name, _)| name == VULKAN_MEMORY_MODEL_EXTENSION);
+
+            let mut features2 = vk::PhysicalDeviceFeatures2::builder();
+            if has_memory_model {
+                features2 = features2.push_next(&mut vk_features.memory_model);
+            }
+            unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+            features2.features
+        } else {
+            unsafe { instance.get_physical_device_features(physical_device) }
+        };
+
+        Self::from_vk(&vk_features)
+    }
+}
+
+/// Features and extensions a caller needs from the device.
+///
+/// Devices lacking anything required are rejected during selection; optional entries are
+/// enabled when available and silently skipped otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceRequirements {
+    pub required_features: Vec<Feature>,
+    pub optional_features: Vec<Feature>,
+    pub required_extensions: Vec<String>,
+    pub optional_extensions: Vec<String>,
+}
+
+impl Default for DeviceRequirements {
+    /// Shaders compiled by rust-gpu use the Vulkan memory model; `VK_EXT_memory_budget` is used
+    /// when available to keep allocations within heap budgets.
+    fn default() -> Self {
+        Self {
+            required_features: vec![Feature::VulkanMemoryModel],
+            optional_features: Vec::new(),
+            required_extensions: Vec::new(),
+            optional_extensions: vec![MEMORY_BUDGET_EXTENSION.to_string()],
+        }
+    }
+}
+
+/// What could not be satisfied by a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingCapabilities {
+    pub features: Vec<Feature>,
+    pub extensions: Vec<String>,
+}
+
+impl fmt::Display for MissingCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut missing: Vec<String> = self.features.iter().map(|f| f.to_string()).collect();
+        missing.extend(self.extensions.iter().cloned());
+
+        write!(f, "missing {}", missing.join(", "))
+    }
+}
+
+/// Features and extensions to enable on the logical device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnabledCapabilities {
+    pub features: FeatureSet,
+    pub extensions: Vec<String>,
+}
+
+impl DeviceRequirements {
+    /// Matches the requirements against what a device supports.
+    pub fn negotiate(
+        &self,
+        supported_features: FeatureSet,
+        supported_extensions: &[(String, u32)],
+        api_version: u32,
+    ) -> Result<EnabledCapabilities, MissingCapabilities> {
+        let is_supported = |name: &String| supported_extensions.iter().any(|(n, _)| n == name);
+
+        let missing = MissingCapabilities {
+            features: self
+                .required_features
+                .iter()
+                .copied()
+                .filter(|&feature| !supported_features.contains(feature))
+                .collect(),
+            extensions: self
+                .required_extensions
+                .iter()
+                .filter(|name| !is_supported(name))
+                .cloned()
+                .collect(),
+        };
+        if !missing.features.is_empty() || !missing.extensions.is_empty() {
+            return Err(missing);
+        }
+
+        let mut enabled = EnabledCapabilities::default();
+        for &feature in self.required_features.iter().chain(&self.optional_features) {
+            if supported_features.contains(feature) {
+                enabled.features.insert(feature);
+            }
+        }
+        for name in self
+            .required_extensions
+            .iter()
+            .chain(&self.optional_extensions)
+        {
+            if is_supported(name) && !enabled.extensions.contains(name) {
+                enabled.extensions.push(name.clone());
+            }
+        }
+
+        // Before Vulkan 1.2 the memory model is only available through its extension.
+        let memory_model_extension = VULKAN_MEMORY_MODEL_EXTENSION.to_string();
+        if api_version < vk::API_VERSION_1_2
+            && enabled.features.iter().any(Feature::is_memory_model)
+            && !enabled.extensions.contains(&memory_model_extension)
+        {
+            enabled.extensions.push(memory_model_extension);
+        }
+
+        Ok(enabled)
+    }
+}
+
+impl EnabledCapabilities {
+    /// Creates the logical device with these features and extensions enabled.
+    pub fn create_device(
+        &self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        queue_create_infos: &[vk::DeviceQueueCreateInfo],
+    ) -> ash::prelude::VkResult<ash::Device> {
+        let mut vk_features = self.features.to_vk();
+
+        let extension_names: Vec<CString> = self
+            .extensions
+            .iter()
+            .map(|name| {
+                CString::new(name.as_str())
+                    .expect("only extensions reported by the driver are enabled")
+            })
+            .collect();
+        let extension_names_ptr: Vec<*const i8> =
+            extension_names.iter().map(|name| name.as_ptr()).collect();
+
+        let mut device_create_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(queue_create_infos)
+            .enabled_features(&vk_features.core)
+            .enabled_extension_names(&extension_names_ptr);
+
+        if self.features.iter().any(Feature::is_memory_model) {
+            device_create_info = device_create_info.push_next(&mut vk_features.memory_model);
+        }
+
+        unsafe { instance.create_device(physical_device, &device_create_info, None) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(features: &[Feature]) -> FeatureSet {
+        let mut set = FeatureSet::default();
+        for &feature in features {
+            set.insert(feature);
+        }
+        set
+    }
+
+    fn extensions(names: &[&str]) -> Vec<(String, u32)> {
+        names.iter().map(|name| (name.to_string(), 1)).collect()
+    }
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_missing_required_capabilities() {
+        let requirements = DeviceRequirements {
+            required_features: vec![Feature::SampleRateShading, Feature::GeometryShader],
+            optional_features: vec![Feature::WideLines],
+            required_extensions: strings(&["VK_KHR_a", "VK_KHR_b"]),
+            optional_extensions: strings(&["VK_KHR_c"]),
+        };
+
+        let missing = requirements
+            .negotiate(
+                features(&[Feature::SampleRateShading]),
+                &extensions(&["VK_KHR_b"]),
+                vk::API_VERSION_1_2,
+            )
+            .unwrap_err();
+        assert_eq!(
+            missing,
+            MissingCapabilities {
+                features: vec![Feature::GeometryShader],
+                extensions: strings(&["VK_KHR_a"]),
+            }
+        );
+        assert_eq!(missing.to_string(), "missing geometry_shader, VK_KHR_a");
+    }
+
+    #[test]
+    fn skips_unsupported_optional_capabilities() {
+        let requirements = DeviceRequirements {
+            required_features: vec![Feature::SampleRateShading],
+            optional_features: vec![Feature::WideLines, Feature::ShaderInt64],
+            required_extensions: strings(&["VK_KHR_a"]),
+            optional_extensions: strings(&["VK_KHR_b", "VK_KHR_c"]),
+        };
+
+        let enabled = requirements
+            .negotiate(
+                features(&[Feature::SampleRateShading, Feature::ShaderInt64]),
+                &extensions(&["VK_KHR_a", "VK_KHR_c"]),
+                vk::API_VERSION_1_2,
+            )
+            .unwrap();
+        assert_eq!(
+            enabled,
+            EnabledCapabilities {
+                features: features(&[Feature::SampleRateShading, Feature::ShaderInt64]),
+                extensions: strings(&["VK_KHR_a", "VK_KHR_c"]),
+            }
+        );
+    }
+
+    #[test]
+    fn enables_each_extension_once() {
+        let requirements = DeviceRequirements {
+            required_features: Vec::new(),
+            optional_features: Vec::new(),
+            required_extensions: strings(&["VK_KHR_a", "VK_KHR_b", "VK_KHR_a"]),
+            optional_extensions: strings(&["VK_KHR_b", "VK_KHR_c"]),
+        };
+
+        let enabled = requirements
+            .negotiate(
+                FeatureSet::default(),
+                &extensions(&["VK_KHR_a", "VK_KHR_b", "VK_KHR_c"]),
+                vk::API_VERSION_1_2,
+            )
+            .unwrap();
+        assert_eq!(
+            enabled.extensions,
+            strings(&["VK_KHR_a", "VK_KHR_b", "VK_KHR_c"])
+        );
+    }
+
+    #[test]
+    fn adds_the_memory_model_extension_before_vulkan_1_2() {
+        let requirements = DeviceRequirements::default();
+        let supported_features = features(&[Feature::VulkanMemoryModel]);
+        let supported_extensions = extensions(&[VULKAN_MEMORY_MODEL_EXTENSION]);
+
+        let enabled = requirements
+            .negotiate(
+                supported_features,
+                &supported_extensions,
+                vk::API_VERSION_1_1,
+            )
+            .unwrap();
+        assert_eq!(enabled.features, supported_features);
+        assert_eq!(
+            enabled.extensions,
+            strings(&[VULKAN_MEMORY_MODEL_EXTENSION])
+        );
+
+        let enabled = requirements
+            .negotiate(
+                supported_features,
+                &supported_extensions,
+                vk::API_VERSION_1_2,
+            )
+            .unwrap();
+        assert!(enabled.extensions.is_empty());
+
+        // Required by the extension itself, so it is not added twice.
+        let requirements = DeviceRequirements {
+            required_extensions: strings(&[VULKAN_MEMORY_MODEL_EXTENSION]),
+            ..DeviceRequirements::default()
+        };
+        let enabled = requirements
+            .negotiate(
+                supported_features,
+                &supported_extensions,
+                vk::API_VERSION_1_1,
+            )
+            .unwrap();
+        assert_eq!(
+            enabled.extensions,
+            strings(&[VULKAN_MEMORY_MODEL_EXTENSION])
+        );
+    }
+}
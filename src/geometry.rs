@@ -0,0 +1,477 @@
+//! Vertex layouts, and geometry uploaded into device-local vertex and index buffers.
+
+use std::{mem, ptr, slice, sync::Arc};
+
+use ash::vk;
+
+use crate::{
+    error::{RenderError, Result},
+    memory::{flush_mapped, MemoryRequest},
+    resource::{Buffer, Device, Fence},
+};
+
+/// Vertex shader entry point of the built-in shader that draws [`MeshVertex`] geometry.
+pub const MESH_ENTRY_POINT: &str = "main_vs_mesh";
+
+/// Binding the vertex buffer is bound to.
+const VERTEX_BINDING: u32 = 0;
+
+/// One attribute of a vertex: where the shader reads it, its format and its byte offset in the
+/// vertex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: vk::Format,
+    pub offset: u32,
+}
+
+/// A vertex type that can be copied into a vertex buffer as it is laid out in memory.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` without padding bytes, and `ATTRIBUTES` must lie within
+/// `size_of::<Self>()`.
+pub unsafe trait Vertex: Copy {
+    const ATTRIBUTES: &'static [VertexAttribute];
+
+    fn binding_description(binding: u32) -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding,
+            stride: mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    fn attribute_descriptions(binding: u32) -> Vec<vk::VertexInputAttributeDescription> {
+        attribute_descriptions(binding, Self::ATTRIBUTES)
+    }
+}
+
+/// The vertex layout [`MESH_ENTRY_POINT`] reads.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    /// Linear RGBA.
+    pub color: [f32; 4],
+}
+
+unsafe impl Vertex for MeshVertex {
+    const ATTRIBUTES: &'static [VertexAttribute] = &[
+        VertexAttribute {
+            location: 0,
+            format: vk::Format::R32G32B32_SFLOAT,
+            offset: 0,
+        },
+        VertexAttribute {
+            location: 1,
+            format: vk::Format::R32G32B32_SFLOAT,
+            offset: 12,
+        },
+        VertexAttribute {
+            location: 2,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 24,
+        },
+        VertexAttribute {
+            location: 3,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            offset: 32,
+        },
+    ];
+}
+
+/// Column-major matrices passed to the vertex shader as push constants, laid out like the
+/// shader's `MeshConstants`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    /// From model space to view space, where the camera looks down -Z.
+    pub model_view: [[f32; 4]; 4],
+    /// From view space to Vulkan clip space, where +Y points down.
+    pub projection: [[f32; 4]; 4],
+}
+
+const IDENTITY: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+impl Default for Transform {
+    /// Passes positions through as clip-space coordinates.
+    fn default() -> Self {
+        Self {
+            model_view: IDENTITY,
+            projection: IDENTITY,
+        }
+    }
+}
+
+impl Transform {
+    fn as_bytes(&self) -> &[u8] {
+        // `repr(C)` arrays of `f32` have no padding.
+        unsafe { slice::from_raw_parts((self as *const Self).cast(), mem::size_of::<Self>()) }
+    }
+}
+
+/// Triangles to draw instead of the built-in one: vertices of any [`Vertex`] type, optionally
+/// indexed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geometry {
+    vertex_data: Vec<u8>,
+    vertex_stride: u32,
+    vertex_count: u32,
+    attributes: Vec<VertexAttribute>,
+    indices: Option<Vec<u32>>,
+    /// Passed to the vertex shader as push constants.
+    pub transform: Transform,
+}
+
+impl Geometry {
+    /// A triangle list drawn in vertex order.
+    pub fn new<V: Vertex>(vertices: &[V]) -> Self {
+        let vertex_data = unsafe {
+            slice::from_raw_parts(vertices.as_ptr().cast::<u8>(), mem::size_of_val(vertices))
+        };
+
+        Self {
+            vertex_data: vertex_data.to_vec(),
+            vertex_stride: mem::size_of::<V>() as u32,
+            vertex_count: vertices.len() as u32,
+            attributes: V::ATTRIBUTES.to_vec(),
+            indices: None,
+            transform: Transform::default(),
+        }
+    }
+
+    /// A triangle list drawn in the order of `indices`.
+    pub fn indexed<V: Vertex>(vertices: &[V], indices: Vec<u32>) -> Self {
+        Self {
+            indices: Some(indices),
+            ..Self::new(vertices)
+        }
+    }
+
+    /// The same triangles with the vertex of every index copied out in draw order, so each
+    /// vertex's index is its position in the list, e.g. for shaders that derive the triangle
+    /// index from the vertex index. Non-indexed geometry is returned as it is.
+    pub fn deindexed(&self) -> Self {
+        let indices = match &self.indices {
+            Some(indices) => indices,
+            None => return self.clone(),
+        };
+
+        let stride = self.vertex_stride as usize;
+        let mut vertex_data = Vec::with_capacity(indices.len() * stride);
+        for &index in indices {
+            let start = index as usize * stride;
+            vertex_data.extend_from_slice(&self.vertex_data[start..start + stride]);
+        }
+
+        Self {
+            vertex_data,
+            vertex_count: indices.len() as u32,
+            indices: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn indices(&self) -> Option<&[u32]> {
+        self.indices.as_deref()
+    }
+
+    /// Checks that there is something to draw and every index refers to a vertex.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.vertex_count == 0 {
+            return Err(RenderError::InvalidConfig(
+                "geometry has no vertices".to_string(),
+            ));
+        }
+        match &self.indices {
+            Some(indices) if indices.is_empty() => Err(RenderError::InvalidConfig(
+                "geometry has no indices".to_string(),
+            )),
+            Some(indices) => match indices.iter().find(|&&i| i >= self.vertex_count) {
+                Some(index) => Err(RenderError::InvalidConfig(format!(
+                    "index {} is out of range for {} vertices",
+                    index, self.vertex_count
+                ))),
+                None => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn binding_description(&self) -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: VERTEX_BINDING,
+            stride: self.vertex_stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    pub(crate) fn attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        attribute_descriptions(VERTEX_BINDING, &self.attributes)
+    }
+
+    /// Push constant range covering [`Transform`].
+    pub(crate) fn push_constant_range() -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: mem::size_of::<Transform>() as u32,
+        }
+    }
+}
+
+fn attribute_descriptions(
+    binding: u32,
+    attributes: &[VertexAttribute],
+) -> Vec<vk::VertexInputAttributeDescription> {
+    attributes
+        .iter()
+        .map(|attribute| vk::VertexInputAttributeDescription {
+            location: attribute.location,
+            binding,
+            format: attribute.format,
+            offset: attribute.offset,
+        })
+        .collect()
+}
+
+/// Device-local vertex and index buffers of a [`Geometry`], and how to draw them.
+pub(crate) struct GeometryBuffers {
+    transform: Transform,
+    draw_count: u32,
+    index_buffer: Option<Buffer>,
+    vertex_buffer: Buffer,
+}
+
+impl GeometryBuffers {
+    /// Uploads validated `geometry` through staging buffers, with copies submitted to `queue` from
+    /// a command buffer of `command_pool`, and waits for them to finish.
+    pub fn upload(
+        device: &Arc<Device>,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        geometry: &Geometry,
+    ) -> Result<Self> {
+        let index_data = geometry.indices.as_ref().map(|indices| unsafe {
+            slice::from_raw_parts(
+                indices.as_ptr().cast::<u8>(),
+                mem::size_of_val(&indices[..]),
+            )
+        });
+
+        let (vertex_buffer, vertex_staging) = create_staged_buffer(
+            device,
+            &geometry.vertex_data,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            "vertex buffer",
+        )?;
+        let index_buffers = index_data
+            .map(|data| {
+                create_staged_buffer(
+                    device,
+                    data,
+                    vk::BufferUsageFlags::INDEX_BUFFER,
+                    "index buffer",
+                )
+            })
+            .transpose()?;
+
+        let mut copies = vec![(&vertex_staging, &vertex_buffer)];
+        if let Some((index_buffer, index_staging)) = &index_buffers {
+            copies.push((index_staging, index_buffer));
+        }
+        submit_copies(device, queue, command_pool, &copies)?;
+
+        Ok(Self {
+            transform: geometry.transform,
+            draw_count: geometry
+                .indices
+                .as_ref()
+                .map_or(geometry.vertex_count, |indices| indices.len() as u32),
+            index_buffer: index_buffers.map(|(index_buffer, _)| index_buffer),
+            vertex_buffer,
+        })
+    }
+
+    /// Records binding the buffers, pushing the transform and drawing one instance.
+    pub fn record_draw(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                self.transform.as_bytes(),
+            );
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                VERTEX_BINDING,
+                &[self.vertex_buffer.handle()],
+                &[0],
+            );
+
+            match &self.index_buffer {
+                Some(index_buffer) => {
+                    device.cmd_bind_index_buffer(
+                        command_buffer,
+                        index_buffer.handle(),
+                        0,
+                        vk::IndexType::UINT32,
+                    );
+                    device.cmd_draw_indexed(command_buffer, self.draw_count, 1, 0, 0, 0);
+                }
+                None => device.cmd_draw(command_buffer, self.draw_count, 1, 0, 0),
+            }
+        }
+    }
+}
+
+/// Creates a device-local buffer for `data` and a host-visible staging buffer holding it.
+fn create_staged_buffer(
+    device: &Arc<Device>,
+    data: &[u8],
+    usage: vk::BufferUsageFlags,
+    name: &str,
+) -> Result<(Buffer, Buffer)> {
+    let size = data.len() as vk::DeviceSize;
+
+    let staging_create_info = vk::BufferCreateInfo::builder()
+        .size(size)
+        .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let staging = Buffer::new(device, &staging_create_info, MemoryRequest::UPLOAD)?;
+    staging.set_name(&format!("{} staging buffer", name))?;
+
+    let mapped = staging
+        .allocation()
+        .mapped_ptr()
+        .expect("upload memory is host visible");
+    unsafe { ptr::copy_nonoverlapping(data.as_ptr(), mapped, data.len()) };
+    flush_mapped(device, staging.allocation())?;
+
+    let buffer_create_info = vk::BufferCreateInfo::builder()
+        .size(size)
+        .usage(usage | vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = Buffer::new(device, &buffer_create_info, MemoryRequest::GPU_ONLY)?;
+    buffer.set_name(name)?;
+
+    Ok((buffer, staging))
+}
+
+/// Copies each `(src, dst)` pair of buffers in one submission and waits for it, so the staging
+/// buffers can be dropped afterwards.
+fn submit_copies(
+    device: &Arc<Device>,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    copies: &[(&Buffer, &Buffer)],
+) -> Result<()> {
+    let command_buffer = {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+
+        unsafe { device.allocate_command_buffers(&allocate_info) }?[0]
+    };
+    device.set_object_name(command_buffer, "upload commands")?;
+
+    let fence = {
+        let fence_create_info = vk::FenceCreateInfo::default();
+        let fence = unsafe { device.create_fence(&fence_create_info, None) }?;
+
+        Fence::from_raw(device, fence)
+    };
+    fence.set_name("upload fence")?;
+
+    let result = (|| -> Result<()> {
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe { device.begin_command_buffer(command_buffer, &begin_info) }?;
+
+        for (src, dst) in copies {
+            let region = vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size: src.size(),
+            };
+            unsafe {
+                device.cmd_copy_buffer(command_buffer, src.handle(), dst.handle(), &[region])
+            };
+        }
+
+        // Make the copies visible to vertex input in the render commands submitted later.
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ)
+            .build();
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            device.end_command_buffer(command_buffer)?;
+        }
+
+        let submit_infos = [vk::SubmitInfo::builder()
+            .command_buffers(&[command_buffer])
+            .build()];
+        unsafe {
+            device.queue_submit(queue, &submit_infos, fence.handle())?;
+            device.wait_for_fences(&[fence.handle()], true, u64::MAX)?;
+        }
+
+        Ok(())
+    })();
+
+    unsafe { device.free_command_buffers(command_pool, &[command_buffer]) };
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deindexed_copies_vertices_in_index_order() {
+        let vertices: Vec<MeshVertex> = (0..4)
+            .map(|i| MeshVertex {
+                position: [i as f32, 0.0, 0.0],
+                ..MeshVertex::default()
+            })
+            .collect();
+        let geometry = Geometry::indexed(&vertices, vec![0, 1, 2, 2, 1, 3]);
+
+        let deindexed = geometry.deindexed();
+        assert_eq!(deindexed.indices(), None);
+        assert_eq!(deindexed.vertex_count(), 6);
+        let expected: Vec<MeshVertex> = [0, 1, 2, 2, 1, 3].iter().map(|&i| vertices[i]).collect();
+        assert_eq!(deindexed, Geometry::new(&expected));
+
+        assert_eq!(deindexed.deindexed(), deindexed);
+    }
+}
@@ -0,0 +1,654 @@
+use std::collections::BTreeMap;
+
+use ash::vk;
+
+/// How the color channels of integer pixels are encoded. Alpha is always linear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Values are proportional to light intensity, as written by the shader to a `UNORM`
+    /// attachment.
+    Linear,
+    /// Values are sRGB-encoded, as stored by `SRGB` attachments and expected by most viewers.
+    Srgb,
+}
+
+/// Tightly packed 8-bit RGBA pixels read back from the GPU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+    pub color_space: ColorSpace,
+}
+
+impl RgbaImage {
+    pub fn row(&self, y: u32) -> &[u8] {
+        let stride = 4 * self.width as usize;
+        let start = y as usize * stride;
+        &self.data[start..start + stride]
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let start = 4 * (y as usize * self.width as usize + x as usize);
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.data[start..start + 4]);
+        pixel
+    }
+
+    /// A copy with sRGB-encoded color channels, for formats that cannot say how their pixels are
+    /// encoded.
+    pub fn to_srgb(&self) -> RgbaImage {
+        match self.color_space {
+            ColorSpace::Srgb => self.clone(),
+            ColorSpace::Linear => RgbaF32Image::from(self).to_rgba_image(),
+        }
+    }
+
+    /// Moves the pixels into an `image` crate buffer without copying them. The color space is not
+    /// carried over.
+    pub fn into_image_buffer(self) -> ::image::RgbaImage {
+        ::image::RgbaImage::from_raw(self.width, self.height, self.data)
+            .expect("data holds width * height RGBA pixels")
+    }
+}
+
+impl From<RgbaImage> for ::image::RgbaImage {
+    fn from(image: RgbaImage) -> Self {
+        image.into_image_buffer()
+    }
+}
+
+/// Tightly packed 16-bit RGBA pixels, for formats with more than 8 bits per channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rgba16Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u16>,
+    pub color_space: ColorSpace,
+}
+
+impl Rgba16Image {
+    pub fn row(&self, y: u32) -> &[u16] {
+        let stride = 4 * self.width as usize;
+        let start = y as usize * stride;
+        &self.data[start..start + stride]
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u16; 4] {
+        let start = 4 * (y as usize * self.width as usize + x as usize);
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.data[start..start + 4]);
+        pixel
+    }
+
+    /// Quantizes the pixels to 8 bits, sRGB-encoded.
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        match self.color_space {
+            ColorSpace::Srgb => RgbaImage {
+                width: self.width,
+                height: self.height,
+                data: self
+                    .data
+                    .iter()
+                    .map(|&value| ((u32::from(value) + 128) / 257) as u8)
+                    .collect(),
+                color_space: ColorSpace::Srgb,
+            },
+            ColorSpace::Linear => RgbaF32Image {
+                width: self.width,
+                height: self.height,
+                data: self
+                    .data
+                    .iter()
+                    .map(|&value| f32::from(value) / 65535.0)
+                    .collect(),
+            }
+            .to_rgba_image(),
+        }
+    }
+}
+
+/// Tightly packed linear RGBA pixels as 32-bit floats, for float color formats whose values are
+/// not limited to `0.0..=1.0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaF32Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl RgbaF32Image {
+    pub fn row(&self, y: u32) -> &[f32] {
+        let stride = 4 * self.width as usize;
+        let start = y as usize * stride;
+        &self.data[start..start + stride]
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [f32; 4] {
+        let start = 4 * (y as usize * self.width as usize + x as usize);
+        let mut pixel = [0.0; 4];
+        pixel.copy_from_slice(&self.data[start..start + 4]);
+        pixel
+    }
+
+    /// Clamps the values to `0.0..=1.0` and quantizes them to 8 bits, sRGB-encoded, without tone
+    /// mapping.
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        RgbaImage {
+            width: self.width,
+            height: self.height,
+            data: self
+                .data
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                    let srgb = |value| unorm8(linear_to_srgb(value));
+                    [srgb(r), srgb(g), srgb(b), unorm8(a)]
+                })
+                .collect(),
+            color_space: ColorSpace::Srgb,
+        }
+    }
+
+    /// Clamps the values to `0.0..=1.0` and quantizes them to linear 16-bit values.
+    pub fn to_rgba16_image(&self) -> Rgba16Image {
+        Rgba16Image {
+            width: self.width,
+            height: self.height,
+            data: self.data.iter().map(|&value| unorm16(value)).collect(),
+            color_space: ColorSpace::Linear,
+        }
+    }
+}
+
+impl From<&RgbaImage> for RgbaF32Image {
+    /// Maps the pixels to `0.0..=1.0`, decoding sRGB to linear.
+    fn from(image: &RgbaImage) -> Self {
+        let decode = |value: u8, alpha: bool| {
+            let value = f32::from(value) / 255.0;
+            match image.color_space {
+                ColorSpace::Srgb if !alpha => srgb_to_linear(value),
+                _ => value,
+            }
+        };
+
+        RgbaF32Image {
+            width: image.width,
+            height: image.height,
+            data: image
+                .data
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| decode(value, i % 4 == 3))
+                .collect(),
+        }
+    }
+}
+
+/// Depth values of the last render, tightly packed row by row.
+///
+/// Values are in `0.0..=1.0` as stored in the depth attachment, 1.0 being the far plane with the
+/// default clear depth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl DepthImage {
+    pub fn row(&self, y: u32) -> &[f32] {
+        let start = y as usize * self.width as usize;
+        &self.data[start..start + self.width as usize]
+    }
+
+    pub fn depth(&self, x: u32, y: u32) -> f32 {
+        self.data[y as usize * self.width as usize + x as usize]
+    }
+
+    /// The depth values quantized to 16 bits, for grayscale images.
+    pub fn to_unorm16(&self) -> Vec<u16> {
+        self.data.iter().map(|&value| unorm16(value)).collect()
+    }
+}
+
+/// Per-pixel IDs read back from an `R32_UINT` attachment, tightly packed row by row.
+///
+/// 0 marks pixels nothing was drawn to, so shaders write IDs plus one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u32>,
+}
+
+impl IdImage {
+    pub fn row(&self, y: u32) -> &[u32] {
+        let start = y as usize * self.width as usize;
+        &self.data[start..start + self.width as usize]
+    }
+
+    pub fn id(&self, x: u32, y: u32) -> u32 {
+        self.data[y as usize * self.width as usize + x as usize]
+    }
+
+    /// Every non-zero ID in ascending order, with the number of pixels it covers.
+    pub fn id_counts(&self) -> Vec<(u32, usize)> {
+        let mut counts = BTreeMap::new();
+        for &id in self.data.iter().filter(|&&id| id != 0) {
+            *counts.entry(id).or_insert(0) += 1;
+        }
+        counts.into_iter().collect()
+    }
+
+    /// Color-codes the IDs with [`id_color`], for viewing.
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        RgbaImage {
+            width: self.width,
+            height: self.height,
+            data: self
+                .data
+                .iter()
+                .flat_map(|&id| {
+                    let [r, g, b] = id_color(id);
+                    [r, g, b, 255]
+                })
+                .collect(),
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+/// The sRGB color of `id` in color-coded ID images: black for 0, and a bright hue for other IDs.
+/// Hues step by the golden ratio, so consecutive IDs get clearly different colors.
+pub fn id_color(id: u32) -> [u8; 3] {
+    if id == 0 {
+        return [0, 0, 0];
+    }
+
+    let hue = (f64::from(id) * 0.618_033_988_749_895).fract() * 6.0;
+    let (saturation, value) = (0.7, 1.0);
+
+    let sector = hue.floor();
+    let fraction = hue - sector;
+    let min = value * (1.0 - saturation);
+    let falling = value * (1.0 - saturation * fraction);
+    let rising = value * (1.0 - saturation * (1.0 - fraction));
+    let rgb = match sector as u32 {
+        0 => [value, rising, min],
+        1 => [falling, value, min],
+        2 => [min, value, rising],
+        3 => [min, falling, value],
+        4 => [rising, min, value],
+        _ => [value, min, falling],
+    };
+
+    [
+        unorm8(rgb[0] as f32),
+        unorm8(rgb[1] as f32),
+        unorm8(rgb[2] as f32),
+    ]
+}
+
+/// The readback memory of the last render, borrowed in place.
+///
+/// Rows are `row_pitch` bytes apart and texels are in the color attachment's `format`, so
+/// `B8G8R8A8_UNORM` pixels are not swizzled. Use [`MappedImage::to_rgba_image`] for an owned copy
+/// in RGBA order.
+#[derive(Debug, Clone, Copy)]
+pub struct MappedImage<'a> {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) format: vk::Format,
+    pub(crate) texel_size: u32,
+    pub(crate) row_pitch: usize,
+    pub(crate) data: &'a [u8],
+}
+
+impl<'a> MappedImage<'a> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    /// Distance between the starts of consecutive rows in bytes.
+    pub fn row_pitch(&self) -> usize {
+        self.row_pitch
+    }
+
+    /// All mapped bytes, including any padding between rows.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The texels of row `y` without padding.
+    pub fn row(&self, y: u32) -> &'a [u8] {
+        let start = y as usize * self.row_pitch;
+        &self.data[start..start + (self.texel_size * self.width) as usize]
+    }
+
+    /// Copies the pixels into a tightly packed RGBA image.
+    ///
+    /// 8-bit formats are copied as stored, in their color space; deeper formats are clamped to
+    /// `0.0..=1.0` and sRGB-encoded. Use [`MappedImage::to_rgba16_image`] or
+    /// [`MappedImage::to_rgba_f32_image`] to keep their precision. `R32_UINT` IDs are
+    /// color-coded, see [`MappedImage::to_id_image`].
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        let color_space = match self.format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::B8G8R8A8_UNORM => ColorSpace::Linear,
+            vk::Format::R8G8B8A8_SRGB => ColorSpace::Srgb,
+            vk::Format::R32_UINT => return self.decode_ids().to_rgba_image(),
+            _ => return self.to_rgba_f32_image().to_rgba_image(),
+        };
+
+        let mut data = Vec::with_capacity((4 * self.width * self.height) as usize);
+        for y in 0..self.height {
+            data.extend_from_slice(self.row(y));
+        }
+
+        if self.format == vk::Format::B8G8R8A8_UNORM {
+            for pixel in data.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        RgbaImage {
+            width: self.width,
+            height: self.height,
+            data,
+            color_space,
+        }
+    }
+
+    /// Copies the pixels into a tightly packed 16-bit RGBA image.
+    ///
+    /// `R16G16B16A16_UNORM` is copied as stored and float formats are clamped to `0.0..=1.0`, all
+    /// linear. 8-bit formats are widened and keep their color space.
+    pub fn to_rgba16_image(&self) -> Rgba16Image {
+        match self.format {
+            vk::Format::R16G16B16A16_UNORM => {}
+            vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32B32A32_SFLOAT => {
+                return self.to_rgba_f32_image().to_rgba16_image();
+            }
+            _ => {
+                let image = self.to_rgba_image();
+                return Rgba16Image {
+                    width: image.width,
+                    height: image.height,
+                    data: image
+                        .data
+                        .iter()
+                        .map(|&value| u16::from(value) * 257)
+                        .collect(),
+                    color_space: image.color_space,
+                };
+            }
+        }
+
+        let mut data = Vec::with_capacity((4 * self.width * self.height) as usize);
+        for y in 0..self.height {
+            data.extend(
+                self.row(y)
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]])),
+            );
+        }
+
+        Rgba16Image {
+            width: self.width,
+            height: self.height,
+            data,
+            color_space: ColorSpace::Linear,
+        }
+    }
+
+    /// Copies the pixels into a tightly packed float RGBA image. Integer formats map to
+    /// `0.0..=1.0`, decoding sRGB to linear.
+    pub fn to_rgba_f32_image(&self) -> RgbaF32Image {
+        let decode: fn(&[u8]) -> f32 = match self.format {
+            vk::Format::R16G16B16A16_SFLOAT => {
+                |bytes| f16_to_f32(u16::from_ne_bytes([bytes[0], bytes[1]]))
+            }
+            vk::Format::R32G32B32A32_SFLOAT => {
+                |bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            }
+            vk::Format::R16G16B16A16_UNORM => {
+                |bytes| f32::from(u16::from_ne_bytes([bytes[0], bytes[1]])) / 65535.0
+            }
+            _ => return RgbaF32Image::from(&self.to_rgba_image()),
+        };
+        let component_size = (self.texel_size / 4) as usize;
+
+        let mut data = Vec::with_capacity((4 * self.width * self.height) as usize);
+        for y in 0..self.height {
+            data.extend(self.row(y).chunks_exact(component_size).map(decode));
+        }
+
+        RgbaF32Image {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+
+    /// Copies the IDs of an `R32_UINT` attachment; `None` for other formats.
+    pub fn to_id_image(&self) -> Option<IdImage> {
+        if self.format == vk::Format::R32_UINT {
+            Some(self.decode_ids())
+        } else {
+            None
+        }
+    }
+
+    fn decode_ids(&self) -> IdImage {
+        let mut data = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            data.extend(
+                self.row(y)
+                    .chunks_exact(4)
+                    .map(|bytes| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            );
+        }
+
+        IdImage {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+
+    /// Converts a depth readback, with texels in the layout of a depth aspect copy of `format`.
+    pub(crate) fn into_depth_image(self) -> DepthImage {
+        let decode: fn(&[u8]) -> f32 = match self.format {
+            vk::Format::D16_UNORM | vk::Format::D16_UNORM_S8_UINT => {
+                |bytes| f32::from(u16::from_ne_bytes([bytes[0], bytes[1]])) / 65535.0
+            }
+            // The upper 8 bits of a 24-bit depth copy are undefined.
+            vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D24_UNORM_S8_UINT => |bytes| {
+                let value = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (value & 0x00ff_ffff) as f32 / 16_777_215.0
+            },
+            _ => |bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        };
+
+        let mut data = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            data.extend(
+                self.row(y)
+                    .chunks_exact(self.texel_size as usize)
+                    .map(decode),
+            );
+        }
+
+        DepthImage {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+}
+
+fn unorm8(value: f32) -> u8 {
+    // NaN stays NaN through the clamp and casts to 0.
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn unorm16(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+/// The sRGB transfer function, for values in `0.0..=1.0`.
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts an IEEE 754 half-precision value to `f32`, including subnormals, infinities and NaN.
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = u32::from(bits >> 15) << 31;
+    let exponent = u32::from(bits >> 10) & 0x1f;
+    let mantissa = u32::from(bits) & 0x3ff;
+
+    let magnitude = match exponent {
+        0 if mantissa == 0 => 0,
+        // Subnormal: mantissa * 2^-24, exact in f32.
+        0 => (mantissa as f32 * (1.0 / 16_777_216.0)).to_bits(),
+        0x1f => 0x7f80_0000 | (mantissa << 13),
+        _ => ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+
+    f32::from_bits(sign | magnitude)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(values: &[f32]) -> Vec<u32> {
+        values.iter().map(|value| value.to_bits()).collect()
+    }
+
+    #[test]
+    fn f16_values() {
+        let f32_bits = |half| f16_to_f32(half).to_bits();
+
+        assert_eq!(f32_bits(0x0000), 0.0f32.to_bits());
+        assert_eq!(f32_bits(0x8000), (-0.0f32).to_bits());
+        // Subnormals: the smallest, the largest and a negative one.
+        assert_eq!(f32_bits(0x0001), 2.0f32.powi(-24).to_bits());
+        assert_eq!(f32_bits(0x03ff), (1023.0 * 2.0f32.powi(-24)).to_bits());
+        assert_eq!(f32_bits(0x8001), (-(2.0f32.powi(-24))).to_bits());
+        assert_eq!(f32_bits(0x7c00), f32::INFINITY.to_bits());
+        assert_eq!(f32_bits(0xfc00), f32::NEG_INFINITY.to_bits());
+        assert!(f16_to_f32(0x7e00).is_nan());
+        assert!(f16_to_f32(0xfc01).is_nan());
+        // Normal values: the smallest, one, a fraction and the largest.
+        assert_eq!(f32_bits(0x0400), 2.0f32.powi(-14).to_bits());
+        assert_eq!(f32_bits(0x3c00), 1.0f32.to_bits());
+        assert_eq!(f32_bits(0xc000), (-2.0f32).to_bits());
+        assert_eq!(f32_bits(0x3555), 0.333_251_95f32.to_bits());
+        assert_eq!(f32_bits(0x7bff), 65504.0f32.to_bits());
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        assert_eq!(unorm8(linear_to_srgb(0.0)), 0);
+        assert_eq!(unorm8(linear_to_srgb(1.0)), 255);
+        assert_eq!(unorm8(srgb_to_linear(0.0)), 0);
+        assert_eq!(unorm8(srgb_to_linear(1.0)), 255);
+
+        for value in 0..=255 {
+            let linear = srgb_to_linear(f32::from(value) / 255.0);
+            assert_eq!(unorm8(linear_to_srgb(linear)), value);
+        }
+    }
+
+    #[test]
+    fn rgba16_to_rgba_image() {
+        let srgb = Rgba16Image {
+            width: 2,
+            height: 1,
+            data: vec![0, 65535, 128, 129, 257, 32896, 65406, 65407],
+            color_space: ColorSpace::Srgb,
+        };
+        assert_eq!(
+            srgb.to_rgba_image(),
+            RgbaImage {
+                width: 2,
+                height: 1,
+                data: vec![0, 255, 0, 1, 1, 128, 254, 255],
+                color_space: ColorSpace::Srgb,
+            }
+        );
+
+        // Linear color is sRGB-encoded, alpha is only quantized.
+        let linear = Rgba16Image {
+            width: 1,
+            height: 1,
+            data: vec![0, 65535, 32896, 32896],
+            color_space: ColorSpace::Linear,
+        };
+        assert_eq!(linear.to_rgba_image().data, [0, 255, 188, 128]);
+    }
+
+    #[test]
+    fn rgba_image_to_f32() {
+        let linear = RgbaImage {
+            width: 1,
+            height: 1,
+            data: vec![0, 255, 51, 128],
+            color_space: ColorSpace::Linear,
+        };
+        assert_eq!(
+            bits(&RgbaF32Image::from(&linear).data),
+            bits(&[0.0, 1.0, 0.2, 128.0 / 255.0])
+        );
+
+        let srgb = RgbaImage {
+            color_space: ColorSpace::Srgb,
+            ..linear
+        };
+        let image = RgbaF32Image::from(&srgb);
+        assert_eq!((image.width, image.height), (1, 1));
+        assert_eq!(
+            bits(&image.data),
+            bits(&[0.0, 1.0, srgb_to_linear(0.2), 128.0 / 255.0])
+        );
+    }
+
+    #[test]
+    fn alpha_stays_linear() {
+        let image = RgbaImage {
+            width: 1,
+            height: 1,
+            data: vec![64, 64, 64, 64],
+            color_space: ColorSpace::Linear,
+        };
+
+        let srgb = image.to_srgb();
+        assert_eq!(srgb.data, [137, 137, 137, 64]);
+        assert_eq!(srgb.color_space, ColorSpace::Srgb);
+        assert_eq!(srgb.to_srgb(), srgb);
+
+        let linear = RgbaF32Image::from(&srgb);
+        assert_eq!(linear.data[3].to_bits(), (64.0f32 / 255.0).to_bits());
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    ffi::{CStr, CString},
+    sync::Arc,
+};
+
+use ash::{extensions::ext::DebugUtils, prelude::VkResult, vk};
+
+use crate::{
+    debug::{DebugMessengerConfig, ValidationSink},
+    error::{RenderError, Result},
+    resource::Instance,
+};
+
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Whether to enable [`VALIDATION_LAYER`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    Disabled,
+    /// Enable the layer when installed, otherwise warn and continue without it.
+    Optional,
+    /// Fail with [`RenderError::LayerNotPresent`] when the layer is not installed.
+    Required,
+}
+
+impl Default for Validation {
+    /// `Optional` in debug builds, `Disabled` in release builds.
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Validation::Optional
+        } else {
+            Validation::Disabled
+        }
+    }
+}
+
+/// Creates a Vulkan 1.2 instance, enabling the Khronos validation layer as requested by
+/// `validation` and `VK_EXT_debug_utils` whenever it is available.
+///
+/// With `VK_EXT_debug_utils` enabled, messages selected by `debug_messenger` are logged for the
+/// lifetime of the instance, including those from instance creation and destruction.
+pub fn create_instance(
+    validation: Validation,
+    debug_messenger: &DebugMessengerConfig,
+) -> Result<Arc<Instance>> {
+    let entry = unsafe { ash::Entry::new() }?;
+
+    let validation_layer = CString::new(VALIDATION_LAYER).unwrap();
+    let validation_enabled = match validation {
+        Validation::Disabled => false,
+        Validation::Optional => {
+            let available = is_layer_available(&entry, &validation_layer)?;
+            if !available {
+                log::warn!(
+                    "{} is not installed, continuing without validation",
+                    VALIDATION_LAYER
+                );
+            }
+            available
+        }
+        Validation::Required => {
+            if !is_layer_available(&entry, &validation_layer)? {
+                return Err(RenderError::LayerNotPresent(validation_layer));
+            }
+            true
+        }
+    };
+    // Enabled even without validation so that objects are named in tools like RenderDoc.
+    let debug_utils_enabled = is_instance_extension_available(&entry, DebugUtils::name())?;
+
+    let layer_names: Vec<*const i8> = if validation_enabled {
+        vec![validation_layer.as_ptr()]
+    } else {
+        Vec::new()
+    };
+    let extension_names: Vec<*const i8> = if debug_utils_enabled {
+        vec![DebugUtils::name().as_ptr()]
+    } else {
+        Vec::new()
+    };
+
+    let application_name = CString::new("Hello Triangle").unwrap();
+    let engine_name = CString::new("No Engine").unwrap();
+
+    if debug_messenger.captures() && !debug_utils_enabled {
+        log::warn!("validation messages cannot be captured without VK_EXT_debug_utils");
+    }
+    let validation_sink = if debug_utils_enabled && debug_messenger.captures() {
+        Some(Arc::new(ValidationSink::new(debug_messenger)))
+    } else {
+        None
+    };
+    let mut debug_utils_create_info = debug_messenger.create_info(validation_sink.as_deref());
+
+    let application_info = vk::ApplicationInfo::builder()
+        .application_name(application_name.as_c_str())
+        .application_version(vk::make_api_version(0, 1, 0, 0))
+        .engine_name(engine_name.as_c_str())
+        .engine_version(vk::make_api_version(0, 1, 0, 0))
+        .api_version(vk::API_VERSION_1_2)
+        .build();
+
+    let instance_create_info = vk::InstanceCreateInfo::builder()
+        .application_info(&application_info)
+        .enabled_layer_names(&layer_names)
+        .enabled_extension_names(&extension_names);
+
+    // Also reports problems in vkCreateInstance and vkDestroyInstance themselves.
+    let instance_create_info = if debug_utils_enabled {
+        instance_create_info.push_next(&mut debug_utils_create_info)
+    } else {
+        instance_create_info
+    }
+    .build();
+
+    let instance = unsafe { entry.create_instance(&instance_create_info, None) }?;
+
+    let instance = Instance::new(entry, instance, validation_enabled);
+    let instance = if debug_utils_enabled {
+        instance.with_debug_messenger(
+            &debug_messenger.create_info(validation_sink.as_deref()),
+            validation_sink,
+        )?
+    } else {
+        instance
+    };
+
+    Ok(Arc::new(instance))
+}
+
+fn is_layer_available(entry: &ash::Entry, name: &CStr) -> VkResult<bool> {
+    Ok(entry
+        .enumerate_instance_layer_properties()?
+        .iter()
+        .any(|layer| unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) } == name))
+}
+
+fn is_instance_extension_available(entry: &ash::Entry, name: &CStr) -> VkResult<bool> {
+    Ok(entry
+        .enumerate_instance_extension_properties()?
+        .iter()
+        .any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == name))
+}
@@ -0,0 +1,55 @@
+pub mod allocator;
+mod config;
+mod debug;
+mod depth;
+pub mod device;
+mod error;
+mod exr;
+pub mod features;
+mod geometry;
+mod image;
+mod instance;
+pub mod memory;
+mod mesh;
+mod multisample;
+mod obj;
+mod output;
+mod ply;
+mod readback;
+mod renderer;
+pub mod report;
+pub mod resource;
+mod stl;
+mod webp;
+
+pub use crate::config::{
+    gbuffer_attachments, id_attachments, parse_clear_color, parse_color_format,
+    ColorAttachmentConfig, RenderConfig, GBUFFER_ENTRY_POINT, ID_ENTRY_POINT, ID_FORMAT,
+    SUPPORTED_COLOR_FORMATS,
+};
+pub use crate::debug::{
+    parse_debug_message_types, parse_debug_severity, DebugMessengerConfig, ValidationMessage,
+};
+pub use crate::depth::{
+    parse_depth_format, DepthConfig, DEPTH_FORMATS, DEPTH_STENCIL_FORMATS, SUPPORTED_DEPTH_FORMATS,
+};
+pub use crate::device::{parse_device_selector, DeviceSelector};
+pub use crate::error::{RenderError, Result};
+pub use crate::features::{DeviceRequirements, Feature};
+pub use crate::geometry::{
+    Geometry, MeshVertex, Transform, Vertex, VertexAttribute, MESH_ENTRY_POINT,
+};
+pub use crate::image::{
+    id_color, ColorSpace, DepthImage, IdImage, MappedImage, Rgba16Image, RgbaF32Image, RgbaImage,
+};
+pub use crate::instance::Validation;
+pub use crate::mesh::{
+    load_mesh, BoundingBox, Mesh, ModelFormat, DEFAULT_VERTEX_COLOR, MODEL_EXTENSIONS,
+};
+pub use crate::multisample::{parse_sample_count, MultisampleConfig, SUPPORTED_SAMPLE_COUNTS};
+pub use crate::output::{
+    write_depth_image, write_hdr_image, write_id_image, write_id_legend, write_image,
+    write_image16, OutputFormat, DEFAULT_JPEG_QUALITY,
+};
+pub use crate::readback::ReadbackMethod;
+pub use crate::renderer::OffscreenRenderer;
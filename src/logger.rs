@@ -0,0 +1,40 @@
+use std::env;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Environment variable overriding the log level, e.g. `RUST_LOG=debug`.
+const LOG_ENV_VAR: &str = "RUST_LOG";
+
+/// Prints log records to stderr in the same `level: message` style as errors.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let level = record.level().to_string().to_ascii_lowercase();
+            if record.target().starts_with("vulkan") {
+                eprintln!("{} [{}]: {}", level, record.target(), record.args());
+            } else {
+                eprintln!("{}: {}", level, record.args());
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the stderr logger at `$RUST_LOG` if set to a level name, else at `default_level`.
+pub fn init(default_level: LevelFilter) {
+    let level = env::var(LOG_ENV_VAR)
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(default_level);
+
+    if log::set_logger(&StderrLogger).is_ok() {
+        log::set_max_level(level);
+    }
+}
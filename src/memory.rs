@@ -0,0 +1,368 @@
+//! Memory type selection.
+
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    allocator::{block_size, Allocation},
+    error::{RenderError, Result},
+    resource::Device,
+};
+
+pub const MEMORY_BUDGET_EXTENSION: &str = "VK_EXT_memory_budget";
+
+/// Property flags an allocation must have, and flags it should have if possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryRequest {
+    pub required: vk::MemoryPropertyFlags,
+    pub preferred: vk::MemoryPropertyFlags,
+}
+
+impl MemoryRequest {
+    /// Render targets and other GPU-only resources.
+    pub const GPU_ONLY: Self = Self {
+        required: vk::MemoryPropertyFlags::empty(),
+        preferred: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    };
+
+    /// Memory the host reads back from. Cached memory makes reads much faster; without
+    /// `HOST_COHERENT` the range has to be invalidated before reading, see [`invalidate_mapped`].
+    pub const READBACK: Self = Self {
+        required: vk::MemoryPropertyFlags::HOST_VISIBLE,
+        preferred: vk::MemoryPropertyFlags::HOST_CACHED,
+    };
+
+    /// Memory the host writes to for upload. Without `HOST_COHERENT` the range has to be flushed
+    /// after writing, see [`flush_mapped`].
+    pub const UPLOAD: Self = Self {
+        required: vk::MemoryPropertyFlags::HOST_VISIBLE,
+        preferred: vk::MemoryPropertyFlags::HOST_COHERENT,
+    };
+}
+
+/// Remaining allowance of a memory heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapBudget {
+    /// How much the process may allocate from the heap in total.
+    pub budget: vk::DeviceSize,
+    /// How much the process has allocated from the heap.
+    pub usage: vk::DeviceSize,
+}
+
+impl HeapBudget {
+    pub fn available(&self) -> vk::DeviceSize {
+        self.budget.saturating_sub(self.usage)
+    }
+}
+
+/// Queries per-heap budgets with `VK_EXT_memory_budget` if `memory_budget` is set, the extension
+/// being enabled on the device; otherwise reports each heap's full size as available.
+pub fn heap_budgets(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    memory_budget: bool,
+) -> Vec<HeapBudget> {
+    let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties2 = vk::PhysicalDeviceMemoryProperties2::builder();
+    if memory_budget {
+        properties2 = properties2.push_next(&mut budget_properties);
+    }
+    unsafe { instance.get_physical_device_memory_properties2(physical_device, &mut properties2) };
+
+    let properties = properties2.memory_properties;
+    properties.memory_heaps[..properties.memory_heap_count as usize]
+        .iter()
+        .enumerate()
+        .map(|(i, heap)| {
+            if memory_budget {
+                HeapBudget {
+                    budget: budget_properties.heap_budget[i],
+                    usage: budget_properties.heap_usage[i],
+                }
+            } else {
+                HeapBudget {
+                    budget: heap.size,
+                    usage: 0,
+                }
+            }
+        })
+        .collect()
+}
+
+/// Picks the memory type for an allocation of `requirements` that has all `request.required`
+/// flags and as many `request.preferred` flags as possible, skipping heaps without `budgets`
+/// left for the memory block the allocator would create for it. Ties go to the lower index, which
+/// drivers order by performance.
+pub fn select_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    requirements: &vk::MemoryRequirements,
+    request: MemoryRequest,
+    budgets: &[HeapBudget],
+) -> Result<u32> {
+    let candidates: Vec<u32> = (0..memory_properties.memory_type_count)
+        .filter(|&i| {
+            requirements.memory_type_bits & (1 << i) != 0
+                && memory_properties.memory_types[i as usize]
+                    .property_flags
+                    .contains(request.required)
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(RenderError::NoMemoryType {
+            type_bits: requirements.memory_type_bits,
+            properties: request.required,
+        });
+    }
+
+    let needed = |i: u32| {
+        let heap_index = memory_properties.memory_types[i as usize].heap_index as usize;
+        block_size(
+            requirements.size,
+            memory_properties.memory_heaps[heap_index].size,
+        )
+    };
+
+    candidates
+        .iter()
+        .copied()
+        .filter(|&i| {
+            let heap_index = memory_properties.memory_types[i as usize].heap_index as usize;
+            let available = budgets
+                .get(heap_index)
+                .map_or(vk::DeviceSize::MAX, HeapBudget::available);
+            available >= needed(i)
+        })
+        .max_by_key(|&i| {
+            let flags = memory_properties.memory_types[i as usize].property_flags;
+            let preferred = (flags & request.preferred).as_raw().count_ones();
+            (preferred, std::cmp::Reverse(i))
+        })
+        .ok_or_else(|| RenderError::MemoryBudgetExceeded {
+            size: candidates.iter().map(|&i| needed(i)).min().unwrap_or(0),
+        })
+}
+
+/// Makes device writes to mapped `allocation` visible to the host. Does nothing for `HOST_COHERENT`
+/// memory.
+pub fn invalidate_mapped(device: &Device, allocation: &Allocation) -> VkResult<()> {
+    if allocation
+        .memory_flags()
+        .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    {
+        return Ok(());
+    }
+
+    // Whole-block ranges avoid rounding to `nonCoherentAtomSize`.
+    let range = whole_range(allocation.memory());
+    unsafe { device.invalidate_mapped_memory_ranges(&[range]) }
+}
+
+/// Makes host writes to mapped `allocation` visible to the device. Does nothing for `HOST_COHERENT`
+/// memory.
+pub fn flush_mapped(device: &Device, allocation: &Allocation) -> VkResult<()> {
+    if allocation
+        .memory_flags()
+        .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    {
+        return Ok(());
+    }
+
+    // Whole-block ranges avoid rounding to `nonCoherentAtomSize`.
+    let range = whole_range(allocation.memory());
+    unsafe { device.flush_mapped_memory_ranges(&[range]) }
+}
+
+fn whole_range(memory: vk::DeviceMemory) -> vk::MappedMemoryRange {
+    vk::MappedMemoryRange::builder()
+        .memory(memory)
+        .offset(0)
+        .size(vk::WHOLE_SIZE)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: vk::DeviceSize = 1024 * 1024;
+    const GIB: vk::DeviceSize = 1024 * MIB;
+
+    /// Memory properties with one type per `(flags, heap index)` and the given heap sizes.
+    fn properties(
+        types: &[(vk::MemoryPropertyFlags, u32)],
+        heaps: &[vk::DeviceSize],
+    ) -> vk::PhysicalDeviceMemoryProperties {
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: types.len() as u32,
+            memory_heap_count: heaps.len() as u32,
+            ..Default::default()
+        };
+        for (i, &(property_flags, heap_index)) in types.iter().enumerate() {
+            properties.memory_types[i] = vk::MemoryType {
+                property_flags,
+                heap_index,
+            };
+        }
+        for (i, &size) in heaps.iter().enumerate() {
+            properties.memory_heaps[i] = vk::MemoryHeap {
+                size,
+                flags: vk::MemoryHeapFlags::empty(),
+            };
+        }
+        properties
+    }
+
+    fn requirements(size: vk::DeviceSize, memory_type_bits: u32) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment: 256,
+            memory_type_bits,
+        }
+    }
+
+    fn unlimited(heaps: usize) -> Vec<HeapBudget> {
+        vec![
+            HeapBudget {
+                budget: vk::DeviceSize::MAX,
+                usage: 0,
+            };
+            heaps
+        ]
+    }
+
+    /// A discrete GPU: device-local memory, then host-visible memory with and without caching.
+    fn discrete() -> vk::PhysicalDeviceMemoryProperties {
+        properties(
+            &[
+                (vk::MemoryPropertyFlags::DEVICE_LOCAL, 0),
+                (
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    1,
+                ),
+                (
+                    vk::MemoryPropertyFlags::HOST_VISIBLE
+                        | vk::MemoryPropertyFlags::HOST_COHERENT
+                        | vk::MemoryPropertyFlags::HOST_CACHED,
+                    1,
+                ),
+            ],
+            &[8 * GIB, 16 * GIB],
+        )
+    }
+
+    #[test]
+    fn prefers_the_most_preferred_flags() {
+        let properties = discrete();
+        let select = |request| {
+            select_memory_type(
+                &properties,
+                &requirements(MIB, 0b111),
+                request,
+                &unlimited(2),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(select(MemoryRequest::GPU_ONLY), 0);
+        assert_eq!(select(MemoryRequest::READBACK), 2);
+        // Both host-visible types are coherent; the lower index wins the tie.
+        assert_eq!(select(MemoryRequest::UPLOAD), 1);
+    }
+
+    #[test]
+    fn falls_back_to_types_without_preferred_flags() {
+        let properties = discrete();
+
+        // Only the uncached type is allowed by the resource.
+        let index = select_memory_type(
+            &properties,
+            &requirements(MIB, 0b011),
+            MemoryRequest::READBACK,
+            &unlimited(2),
+        )
+        .unwrap();
+        assert_eq!(index, 1);
+
+        // Nothing device-local is allowed, so GPU-only resources end up in host memory.
+        let index = select_memory_type(
+            &properties,
+            &requirements(MIB, 0b110),
+            MemoryRequest::GPU_ONLY,
+            &unlimited(2),
+        )
+        .unwrap();
+        assert_eq!(index, 1);
+    }
+
+    #[test]
+    fn required_flags_are_never_dropped() {
+        let result = select_memory_type(
+            &discrete(),
+            &requirements(MIB, 0b001),
+            MemoryRequest::READBACK,
+            &unlimited(2),
+        );
+
+        assert!(matches!(
+            result,
+            Err(RenderError::NoMemoryType { type_bits: 0b001, properties })
+                if properties == vk::MemoryPropertyFlags::HOST_VISIBLE
+        ));
+    }
+
+    #[test]
+    fn budget_covers_the_block_not_just_the_resource() {
+        let properties = discrete();
+        // 40 MiB left on the device-local heap: enough for the resource, not for a 64 MiB block.
+        let budgets = [
+            HeapBudget {
+                budget: 8 * GIB,
+                usage: 8 * GIB - 40 * MIB,
+            },
+            HeapBudget {
+                budget: 16 * GIB,
+                usage: 0,
+            },
+        ];
+
+        let index = select_memory_type(
+            &properties,
+            &requirements(MIB, 0b111),
+            MemoryRequest::GPU_ONLY,
+            &budgets,
+        )
+        .unwrap();
+        assert_eq!(index, 1);
+
+        // Large resources get a dedicated block of their own size, which still fits.
+        let index = select_memory_type(
+            &properties,
+            &requirements(40 * MIB, 0b111),
+            MemoryRequest::GPU_ONLY,
+            &budgets,
+        )
+        .unwrap();
+        assert_eq!(index, 0);
+    }
+
+    #[test]
+    fn budget_exceeded_reports_the_smallest_block() {
+        let properties = properties(&[(vk::MemoryPropertyFlags::DEVICE_LOCAL, 0)], &[256 * MIB]);
+        let budgets = [HeapBudget {
+            budget: 256 * MIB,
+            usage: 256 * MIB - MIB,
+        }];
+
+        let result = select_memory_type(
+            &properties,
+            &requirements(MIB, 0b1),
+            MemoryRequest::GPU_ONLY,
+            &budgets,
+        );
+
+        // Blocks are at most an eighth of the heap.
+        assert!(matches!(
+            result,
+            Err(RenderError::MemoryBudgetExceeded { size }) if size == 32 * MIB
+        ));
+    }
+}
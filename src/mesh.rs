@@ -0,0 +1,410 @@
+//! Triangle meshes loaded from model files, with normal generation and framing.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::{RenderError, Result},
+    geometry::{Geometry, MeshVertex, Transform},
+    obj, ply, stl,
+};
+
+/// Model file extensions [`load_mesh`] reads, with the format of each.
+pub const MODEL_EXTENSIONS: &[(&str, ModelFormat)] = &[
+    ("obj", ModelFormat::Obj),
+    ("stl", ModelFormat::Stl),
+    ("ply", ModelFormat::Ply),
+];
+
+/// Color of vertices whose file gives them none.
+pub const DEFAULT_VERTEX_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// Vertical field of view of [`BoundingBox::framing_transform`], in radians.
+const FIELD_OF_VIEW: f32 = std::f32::consts::FRAC_PI_4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelFormat {
+    /// Wavefront OBJ, with diffuse colors from the MTL files it references.
+    Obj,
+    /// Binary or ASCII STL.
+    Stl,
+    /// ASCII or binary PLY.
+    Ply,
+}
+
+impl ModelFormat {
+    /// The format matching the extension of `path`, ignoring case.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+
+        MODEL_EXTENSIONS
+            .iter()
+            .find(|&&(name, _)| name.eq_ignore_ascii_case(extension))
+            .map(|&(_, format)| format)
+    }
+}
+
+/// An indexed triangle list.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Mesh {
+    pub vertices: Vec<MeshVertex>,
+    /// Three per triangle.
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// The box around every vertex, or `None` without vertices.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        let mut vertices = self.vertices.iter();
+        let first = vertices.next()?.position;
+
+        Some(vertices.fold(
+            BoundingBox {
+                min: first,
+                max: first,
+            },
+            |mut bounds, vertex| {
+                for axis in 0..3 {
+                    bounds.min[axis] = bounds.min[axis].min(vertex.position[axis]);
+                    bounds.max[axis] = bounds.max[axis].max(vertex.position[axis]);
+                }
+                bounds
+            },
+        ))
+    }
+
+    /// Replaces every normal with the area-weighted average of the normals of the triangles
+    /// sharing the vertex, so shared vertices shade smoothly and unshared ones flat.
+    pub fn generate_normals(&mut self) {
+        let mut normals = vec![[0.0f32; 3]; self.vertices.len()];
+
+        for triangle in self.indices.chunks_exact(3) {
+            let corner = |i: usize| self.vertices[triangle[i] as usize].position;
+            // Twice the triangle's area long, which weights larger triangles more.
+            let normal = cross(sub(corner(1), corner(0)), sub(corner(2), corner(0)));
+
+            for &index in triangle {
+                let sum = &mut normals[index as usize];
+                for axis in 0..3 {
+                    sum[axis] += normal[axis];
+                }
+            }
+        }
+
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.normal = normalize(normal);
+        }
+    }
+
+    /// Appends `other`, keeping its indices pointing at its own vertices.
+    pub(crate) fn append(&mut self, other: Mesh) {
+        let offset = self.vertices.len() as u32;
+
+        self.vertices.extend(other.vertices);
+        self.indices
+            .extend(other.indices.into_iter().map(|index| index + offset));
+    }
+
+    /// Checks the mesh has triangles and every index refers to a vertex.
+    pub(crate) fn validate(&self) -> std::result::Result<(), String> {
+        if self.indices.is_empty() {
+            return Err("no triangles".to_string());
+        }
+        match self
+            .indices
+            .iter()
+            .find(|&&index| index as usize >= self.vertices.len())
+        {
+            Some(index) => Err(format!(
+                "vertex index {} is out of range for {} vertices",
+                index,
+                self.vertices.len()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Vertex and index buffers for [`MESH_ENTRY_POINT`](crate::MESH_ENTRY_POINT), with an identity
+    /// transform.
+    pub fn to_geometry(&self) -> Geometry {
+        Geometry::indexed(&self.vertices, self.indices.clone())
+    }
+}
+
+/// An axis-aligned box in model space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl BoundingBox {
+    pub fn center(&self) -> [f32; 3] {
+        let mut center = [0.0; 3];
+        for (axis, center) in center.iter_mut().enumerate() {
+            *center = (self.min[axis] + self.max[axis]) * 0.5;
+        }
+        center
+    }
+
+    pub fn size(&self) -> [f32; 3] {
+        sub(self.max, self.min)
+    }
+
+    /// Radius of the sphere through the corners.
+    pub fn radius(&self) -> f32 {
+        length(self.size()) * 0.5
+    }
+
+    /// A perspective view of the box from its +Z side, with +Y up, that fits it into an image
+    /// `aspect` times as wide as it is high, and maps it into the depth range.
+    pub fn framing_transform(&self, aspect: f32) -> Transform {
+        // Flat or point-like models still get a usable view.
+        let radius = self.radius().max(f32::EPSILON);
+        let half_fov = FIELD_OF_VIEW * 0.5;
+        // The bounding sphere touches the narrower side of the view.
+        let half_fov_narrow = if aspect < 1.0 {
+            (half_fov.tan() * aspect).atan()
+        } else {
+            half_fov
+        };
+        let distance = radius / half_fov_narrow.sin();
+        let near = (distance - radius).max(distance * 0.01);
+        let far = distance + radius;
+
+        let [x, y, z] = self.center();
+        let model_view = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [-x, -y, -z - distance, 1.0],
+        ];
+
+        // Right-handed view space looking down -Z into Vulkan clip space, where +Y points down and
+        // depth goes from 0 at `near` to 1 at `far`.
+        let focal = 1.0 / half_fov.tan();
+        let projection = [
+            [focal / aspect, 0.0, 0.0, 0.0],
+            [0.0, -focal, 0.0, 0.0],
+            [0.0, 0.0, far / (near - far), -1.0],
+            [0.0, 0.0, near * far / (near - far), 0.0],
+        ];
+
+        Transform {
+            model_view,
+            projection,
+        }
+    }
+}
+
+/// Loads the model at `path`, picking the format by extension, and generates normals for
+/// vertices the file gives none.
+pub fn load_mesh(path: &Path) -> Result<Mesh> {
+    let format = ModelFormat::from_extension(path).ok_or_else(|| {
+        let supported: Vec<_> = MODEL_EXTENSIONS
+            .iter()
+            .map(|&(name, _)| format!(".{}", name))
+            .collect();
+        model_error(
+            path,
+            format!("unknown model format, expected {}", supported.join(", ")),
+        )
+    })?;
+
+    let mesh = match format {
+        ModelFormat::Obj => obj::load(path),
+        ModelFormat::Stl => std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| stl::parse(&data)),
+        ModelFormat::Ply => std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| ply::parse(&data)),
+    }
+    .map_err(|message| model_error(path, message))?;
+
+    mesh.validate()
+        .map_err(|message| model_error(path, message))?;
+
+    Ok(mesh)
+}
+
+fn model_error(path: &Path, message: String) -> RenderError {
+    RenderError::Model {
+        path: PathBuf::from(path),
+        message,
+    }
+}
+
+pub(crate) fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub(crate) fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn length(v: [f32; 3]) -> f32 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+/// `v` scaled to unit length, or +Z if it has none, so shaders never normalize a zero vector.
+pub(crate) fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = length(v);
+    if length > 0.0 && length.is_finite() {
+        [v[0] / length, v[1] / length, v[2] / length]
+    } else {
+        [0.0, 0.0, 1.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(positions: &[[f32; 3]], indices: &[u32]) -> Mesh {
+        Mesh {
+            vertices: positions
+                .iter()
+                .map(|&position| MeshVertex {
+                    position,
+                    ..MeshVertex::default()
+                })
+                .collect(),
+            indices: indices.to_vec(),
+        }
+    }
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        let distance = length(sub(actual, expected));
+        assert!(distance < 1e-6, "{:?} is not {:?}", actual, expected);
+    }
+
+    /// Column-major `matrix` times `v`.
+    fn transform(matrix: &[[f32; 4]; 4], v: [f32; 4]) -> [f32; 4] {
+        let mut result = [0.0; 4];
+        for (column, &component) in matrix.iter().zip(&v) {
+            for (row, result) in result.iter_mut().enumerate() {
+                *result += column[row] * component;
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn normals_are_area_weighted() {
+        // Vertex 0 is shared by a triangle facing +Z with four times the area of one facing +X.
+        let mut mesh = mesh(
+            &[
+                [0.0, 0.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [0.0, 2.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+            &[0, 1, 2, 0, 3, 4],
+        );
+        mesh.generate_normals();
+
+        let shared = 1.0 / 17.0f32.sqrt();
+        assert_close(mesh.vertices[0].normal, [shared, 0.0, 4.0 * shared]);
+        assert_close(mesh.vertices[1].normal, [0.0, 0.0, 1.0]);
+        assert_close(mesh.vertices[2].normal, [0.0, 0.0, 1.0]);
+        assert_close(mesh.vertices[3].normal, [1.0, 0.0, 0.0]);
+        assert_close(mesh.vertices[4].normal, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn zero_area_normals_point_to_z() {
+        // A degenerate triangle, and a vertex no triangle uses.
+        let mut mesh = mesh(
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 1.0, 1.0],
+                [2.0, 2.0, 2.0],
+                [5.0, 0.0, 0.0],
+            ],
+            &[0, 1, 2],
+        );
+        mesh.generate_normals();
+
+        for vertex in &mesh.vertices {
+            assert_close(vertex.normal, [0.0, 0.0, 1.0]);
+        }
+        assert_close(normalize([0.0, f32::NAN, 0.0]), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn bounds() {
+        assert_eq!(Mesh::default().bounds(), None);
+
+        let bounds = mesh(
+            &[[1.0, -2.0, 3.0], [-1.0, 4.0, 0.5], [0.0, 0.0, -6.0]],
+            &[0, 1, 2],
+        )
+        .bounds()
+        .unwrap();
+        assert_eq!(
+            bounds,
+            BoundingBox {
+                min: [-1.0, -2.0, -6.0],
+                max: [1.0, 4.0, 3.0],
+            }
+        );
+        assert_close(bounds.center(), [0.0, 1.0, -1.5]);
+        assert_close(bounds.size(), [2.0, 6.0, 9.0]);
+    }
+
+    #[test]
+    fn framing_keeps_corners_in_view() {
+        let boxes = [
+            BoundingBox {
+                min: [-1.0, -2.0, -3.0],
+                max: [3.0, 1.0, 0.0],
+            },
+            // Flat, and a single point.
+            BoundingBox {
+                min: [10.0, 10.0, 0.0],
+                max: [12.0, 11.0, 0.0],
+            },
+            BoundingBox {
+                min: [1.0, 1.0, 1.0],
+                max: [1.0, 1.0, 1.0],
+            },
+        ];
+
+        for bounds in &boxes {
+            for &aspect in &[0.5, 1.0, 16.0 / 9.0] {
+                let framing = bounds.framing_transform(aspect);
+                let project = |[x, y, z]: [f32; 3]| {
+                    let view = transform(&framing.model_view, [x, y, z, 1.0]);
+                    let clip = transform(&framing.projection, view);
+                    assert!(clip[3] > 0.0);
+                    [clip[0] / clip[3], clip[1] / clip[3], clip[2] / clip[3]]
+                };
+
+                let center = project(bounds.center());
+                assert!(center[0].abs() < 1e-5 && center[1].abs() < 1e-5);
+
+                for corner in 0..8 {
+                    let pick = |axis: usize| {
+                        if corner & (1 << axis) == 0 {
+                            bounds.min[axis]
+                        } else {
+                            bounds.max[axis]
+                        }
+                    };
+                    let [x, y, depth] = project([pick(0), pick(1), pick(2)]);
+                    assert!(x.abs() <= 1.0 && y.abs() <= 1.0, "{:?} {}", bounds, aspect);
+                    assert!((0.0..=1.0).contains(&depth), "{:?} {}", bounds, aspect);
+                }
+            }
+        }
+    }
+}
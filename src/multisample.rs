@@ -0,0 +1,148 @@
+//! Multisample anti-aliasing configuration and sample count negotiation.
+
+use ash::vk;
+
+use crate::{
+    config::ID_FORMAT,
+    features::{DeviceRequirements, Feature},
+};
+
+/// Sample counts by number of samples per pixel, for [`parse_sample_count`].
+pub const SUPPORTED_SAMPLE_COUNTS: &[(u32, vk::SampleCountFlags)] = &[
+    (1, vk::SampleCountFlags::TYPE_1),
+    (2, vk::SampleCountFlags::TYPE_2),
+    (4, vk::SampleCountFlags::TYPE_4),
+    (8, vk::SampleCountFlags::TYPE_8),
+];
+
+/// Settings for rendering into multisampled color attachments resolved into the ones read back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultisampleConfig {
+    /// Samples per pixel, one of [`SUPPORTED_SAMPLE_COUNTS`]. Lowered to the highest count the
+    /// device supports for the color formats, and the depth format if there is one.
+    pub samples: vk::SampleCountFlags,
+    /// Shades at least this fraction of the samples of each pixel individually instead of once
+    /// per pixel, which also smooths edges inside triangles. Needs the `sample_rate_shading`
+    /// feature; `None` shades once per pixel.
+    pub min_sample_shading: Option<f32>,
+}
+
+impl Default for MultisampleConfig {
+    fn default() -> Self {
+        Self {
+            samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: None,
+        }
+    }
+}
+
+impl MultisampleConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.samples != vk::SampleCountFlags::TYPE_1
+    }
+
+    /// Adds the features the settings need to `requirements`.
+    pub(crate) fn add_requirements(&self, requirements: &mut DeviceRequirements) {
+        if self.min_sample_shading.is_some()
+            && !requirements
+                .required_features
+                .contains(&Feature::SampleRateShading)
+        {
+            requirements
+                .required_features
+                .push(Feature::SampleRateShading);
+        }
+    }
+}
+
+/// Parses a number of samples per pixel such as `4`, or `4x`.
+pub fn parse_sample_count(s: &str) -> Option<vk::SampleCountFlags> {
+    let count: u32 = s.strip_suffix('x').unwrap_or(s).parse().ok()?;
+
+    SUPPORTED_SAMPLE_COUNTS
+        .iter()
+        .find(|&&(n, _)| n == count)
+        .map(|&(_, samples)| samples)
+}
+
+/// Picks the highest sample count up to `config.samples` that `physical_device` supports for
+/// attachments of every one of `color_formats`, and of `depth_format` if there is one.
+///
+/// Integer color formats such as [`ID_FORMAT`] are also limited by
+/// `framebufferIntegerColorSampleCounts` on Vulkan 1.2 devices; older ones only report them
+/// through the format's own sample counts.
+pub(crate) fn select_sample_count(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    config: &MultisampleConfig,
+    color_formats: &[vk::Format],
+    depth_format: Option<vk::Format>,
+) -> vk::SampleCountFlags {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let limits = properties.limits;
+
+    let mut supported = limits.framebuffer_color_sample_counts;
+    if properties.api_version >= vk::API_VERSION_1_2 && color_formats.contains(&ID_FORMAT) {
+        let mut vulkan_12_properties = vk::PhysicalDeviceVulkan12Properties::default();
+        let mut properties2 =
+            vk::PhysicalDeviceProperties2::builder().push_next(&mut vulkan_12_properties);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+        supported &= vulkan_12_properties.framebuffer_integer_color_sample_counts;
+    }
+    for &color_format in color_formats {
+        supported &= format_sample_counts(
+            instance,
+            physical_device,
+            color_format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        );
+    }
+    if let Some(depth_format) = depth_format {
+        supported &= limits.framebuffer_depth_sample_counts
+            & format_sample_counts(
+                instance,
+                physical_device,
+                depth_format,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            );
+    }
+
+    let samples = SUPPORTED_SAMPLE_COUNTS
+        .iter()
+        .rev()
+        .map(|&(_, samples)| samples)
+        .filter(|&samples| samples.as_raw() <= config.samples.as_raw())
+        .find(|&samples| supported.contains(samples))
+        .unwrap_or(vk::SampleCountFlags::TYPE_1);
+
+    if samples != config.samples {
+        log::warn!(
+            "{} samples per pixel are not supported, using {}",
+            config.samples.as_raw(),
+            samples.as_raw()
+        );
+    }
+
+    samples
+}
+
+fn format_sample_counts(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+) -> vk::SampleCountFlags {
+    unsafe {
+        instance.get_physical_device_image_format_properties(
+            physical_device,
+            format,
+            vk::ImageType::TYPE_2D,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::ImageCreateFlags::empty(),
+        )
+    }
+    .map_or(vk::SampleCountFlags::TYPE_1, |properties| {
+        properties.sample_counts
+    })
+}
@@ -0,0 +1,175 @@
+//! Wavefront OBJ loading through `tobj`.
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use crate::{
+    geometry::MeshVertex,
+    mesh::{Mesh, DEFAULT_VERTEX_COLOR},
+};
+
+/// Loads every object in the OBJ file at `path` into one mesh. Faces are triangulated as fans,
+/// and points and lines are skipped.
+///
+/// Vertices are colored by their own color if the file gives one, else by the diffuse color and
+/// dissolve of their material. Missing or unreadable MTL files only leave the default color.
+pub fn load(path: &Path) -> Result<Mesh, String> {
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true,
+    };
+    // Open the file here so a missing one is reported with its reason.
+    let mut reader = BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+    let (models, materials) = tobj::load_obj_buf(&mut reader, &options, |mtl_path| {
+        tobj::load_mtl(
+            path.parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(mtl_path),
+        )
+    })
+    .map_err(|err| err.to_string())?;
+
+    let materials = materials.unwrap_or_else(|err| {
+        log::warn!("ignoring materials of {}: {}", path.display(), err);
+        Vec::new()
+    });
+
+    let mut mesh = Mesh::default();
+    for model in models {
+        let material_color =
+            model
+                .mesh
+                .material_id
+                .and_then(|id| materials.get(id))
+                .map(|material| {
+                    let [r, g, b] = material.diffuse;
+                    [r, g, b, material.dissolve]
+                });
+
+        mesh.append(convert(
+            &model.mesh,
+            material_color.unwrap_or(DEFAULT_VERTEX_COLOR),
+        ));
+    }
+
+    Ok(mesh)
+}
+
+fn convert(mesh: &tobj::Mesh, color: [f32; 4]) -> Mesh {
+    let vertex_count = mesh.positions.len() / 3;
+    // Attributes are only usable when every vertex has one.
+    let has_normals = mesh.normals.len() == vertex_count * 3;
+    let has_uvs = mesh.texcoords.len() == vertex_count * 2;
+    let has_colors = mesh.vertex_color.len() == vertex_count * 3;
+
+    let vertices = (0..vertex_count)
+        .map(|i| {
+            let vec3 = |values: &[f32]| [values[i * 3], values[i * 3 + 1], values[i * 3 + 2]];
+
+            MeshVertex {
+                position: vec3(&mesh.positions),
+                normal: if has_normals {
+                    vec3(&mesh.normals)
+                } else {
+                    [0.0; 3]
+                },
+                // OBJ puts the origin of texture space at the bottom left, Vulkan at the top left.
+                uv: if has_uvs {
+                    [mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]]
+                } else {
+                    [0.0; 2]
+                },
+                color: if has_colors {
+                    let [r, g, b] = vec3(&mesh.vertex_color);
+                    [r, g, b, color[3]]
+                } else {
+                    color
+                },
+            }
+        })
+        .collect();
+
+    let mut mesh = Mesh {
+        vertices,
+        indices: mesh.indices.clone(),
+    };
+    if !has_normals {
+        mesh.generate_normals();
+    }
+
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Writes `contents` to a file in the temporary directory, loads it and removes it again.
+    fn load_str(name: &str, contents: &str) -> Result<Mesh, String> {
+        let path =
+            std::env::temp_dir().join(format!("obj-test-{}-{}.obj", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let mesh = load(&path);
+        fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn without_normals() {
+        let mesh = load_str(
+            "quad",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f 1/1 2/2 3/3 4/4\n",
+        )
+        .unwrap();
+
+        let uvs = [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+        let positions = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        let vertices: Vec<MeshVertex> = positions
+            .iter()
+            .zip(&uvs)
+            .map(|(&position, &uv)| MeshVertex {
+                position,
+                // Generated from the triangles, which face +Z.
+                normal: [0.0, 0.0, 1.0],
+                uv,
+                color: DEFAULT_VERTEX_COLOR,
+            })
+            .collect();
+        assert_eq!(
+            mesh,
+            Mesh {
+                vertices,
+                indices: vec![0, 1, 2, 0, 2, 3],
+            }
+        );
+    }
+
+    #[test]
+    fn missing_materials_keep_the_default_color() {
+        let mesh = load_str(
+            "material",
+            "mtllib missing.mtl\nusemtl red\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n",
+        )
+        .unwrap();
+
+        assert_eq!(mesh.triangle_count(), 1);
+        assert!(mesh
+            .vertices
+            .iter()
+            .all(|vertex| vertex.color == DEFAULT_VERTEX_COLOR));
+    }
+
+    #[test]
+    fn missing_file() {
+        assert!(load(Path::new("does/not/exist.obj")).is_err());
+    }
+}
@@ -0,0 +1,494 @@
+//! Encoding rendered images into files or streams.
+
+use std::{fmt, io::Write, path::Path, str::FromStr};
+
+use ::image::{
+    codecs::{
+        hdr::HdrEncoder,
+        jpeg::JpegEncoder,
+        pnm::{PNMSubtype, PnmEncoder, SampleEncoding},
+        tga::TgaEncoder,
+    },
+    ColorType, Rgb,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    error::{RenderError, Result},
+    exr,
+    image::{id_color, ColorSpace, DepthImage, IdImage, Rgba16Image, RgbaF32Image, RgbaImage},
+    webp,
+};
+
+/// `sRGB` chunk rendering intent.
+const PNG_SRGB_PERCEPTUAL: u8 = 0;
+/// `gAMA` chunk values: the encoding exponent times 100000, 1/2.2 being what the PNG
+/// specification prescribes alongside an `sRGB` chunk.
+const PNG_GAMMA_SRGB: u32 = 45455;
+const PNG_GAMMA_LINEAR: u32 = 100_000;
+
+/// JPEG quality used when none is given.
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Container format an image is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    /// Baseline JPEG with `quality` in `1..=100`. Alpha is dropped.
+    Jpeg {
+        quality: u8,
+    },
+    /// Lossless WebP.
+    WebP,
+    /// Uncompressed 32-bit TGA.
+    Tga,
+    /// Binary PPM (`P6`). Alpha is dropped.
+    Ppm,
+    /// Tightly packed RGBA8 rows without a header.
+    Raw,
+    /// Uncompressed OpenEXR with 32-bit float RGBA channels.
+    Exr,
+    /// Radiance RGBE (`.hdr`). Alpha is dropped.
+    Hdr,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Png
+    }
+}
+
+impl OutputFormat {
+    /// Format names accepted by [`FromStr`], for help and error messages.
+    pub const NAMES: &'static [&'static str] =
+        &["png", "jpeg", "webp", "tga", "ppm", "raw", "exr", "hdr"];
+
+    /// Guesses the format from the extension of `path`, ignoring case.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        path.extension()?
+            .to_str()?
+            .to_ascii_lowercase()
+            .parse()
+            .ok()
+    }
+
+    /// The usual file extension for the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg { .. } => "jpg",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Tga => "tga",
+            OutputFormat::Ppm => "ppm",
+            OutputFormat::Raw => "raw",
+            OutputFormat::Exr => "exr",
+            OutputFormat::Hdr => "hdr",
+        }
+    }
+
+    /// Whether [`write_depth_image`] supports the format.
+    pub fn stores_depth(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Png | OutputFormat::Exr | OutputFormat::Raw
+        )
+    }
+
+    /// Whether the format stores floats, so values outside `0.0..=1.0` survive.
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, OutputFormat::Exr | OutputFormat::Hdr)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ();
+
+    /// Parses a format name or extension such as `png`, `jpg` or `webp`. JPEG gets
+    /// [`DEFAULT_JPEG_QUALITY`].
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s {
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg {
+                quality: DEFAULT_JPEG_QUALITY,
+            }),
+            "webp" => Ok(OutputFormat::WebP),
+            "tga" => Ok(OutputFormat::Tga),
+            "ppm" => Ok(OutputFormat::Ppm),
+            "raw" | "rgba" => Ok(OutputFormat::Raw),
+            "exr" => Ok(OutputFormat::Exr),
+            "hdr" => Ok(OutputFormat::Hdr),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputFormat::Png => f.write_str("PNG"),
+            OutputFormat::Jpeg { quality } => write!(f, "JPEG (quality {})", quality),
+            OutputFormat::WebP => f.write_str("WebP"),
+            OutputFormat::Tga => f.write_str("TGA"),
+            OutputFormat::Ppm => f.write_str("PPM"),
+            OutputFormat::Raw => f.write_str("raw RGBA"),
+            OutputFormat::Exr => f.write_str("OpenEXR"),
+            OutputFormat::Hdr => f.write_str("Radiance HDR"),
+        }
+    }
+}
+
+/// Encodes `image` as `format` into `writer`, which can be a file, a buffer or stdout.
+///
+/// Linear images are converted to sRGB, which 8 bits per channel are too few to store linear
+/// values in without visible banding, except for raw output. PNG gets an `sRGB` chunk on top. HDR
+/// formats get the pixels mapped to linear `0.0..=1.0`.
+///
+/// `writer` is not buffered here; wrap unbuffered writers in a `BufWriter`.
+pub fn write_image<W: Write>(image: &RgbaImage, format: OutputFormat, mut writer: W) -> Result<()> {
+    let (width, height) = (image.width, image.height);
+    let srgb = || image.to_srgb();
+
+    match format {
+        OutputFormat::Png => {
+            let mut png_encoder = png::Encoder::new(&mut writer, width, height);
+
+            png_encoder.set_depth(png::BitDepth::Eight);
+            png_encoder.set_color(png::ColorType::RGBA);
+
+            let mut png_writer = png_encoder.write_header()?;
+            write_png_color_space(&mut png_writer, ColorSpace::Srgb)?;
+            // Rows are tightly packed, so the data can go out in one piece.
+            png_writer.write_image_data(&srgb().data)?;
+        }
+        OutputFormat::Jpeg { quality } => {
+            JpegEncoder::new_with_quality(&mut writer, quality).encode(
+                &srgb().data,
+                width,
+                height,
+                ColorType::Rgba8,
+            )?;
+        }
+        OutputFormat::WebP => webp::encode(&mut writer, &srgb().data, width, height)?,
+        OutputFormat::Tga => {
+            TgaEncoder::new(&mut writer).encode(&srgb().data, width, height, ColorType::Rgba8)?
+        }
+        OutputFormat::Ppm => {
+            PnmEncoder::new(&mut writer)
+                .with_subtype(PNMSubtype::Pixmap(SampleEncoding::Binary))
+                .encode(rgb(&srgb()).as_slice(), width, height, ColorType::Rgb8)?;
+        }
+        OutputFormat::Raw => writer.write_all(&image.data)?,
+        OutputFormat::Exr | OutputFormat::Hdr => {
+            return write_hdr_image(&RgbaF32Image::from(image), format, writer);
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Encodes 16-bit `image` as `format` into `writer`, like [`write_image`].
+///
+/// PNG is written with 16 bits per channel, linear images with a `gAMA` chunk of 1.0; the other
+/// formats get the image quantized to 8 bits.
+pub fn write_image16<W: Write>(
+    image: &Rgba16Image,
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<()> {
+    if format != OutputFormat::Png {
+        return write_image(&image.to_rgba_image(), format, writer);
+    }
+
+    let mut png_encoder = png::Encoder::new(&mut writer, image.width, image.height);
+
+    png_encoder.set_depth(png::BitDepth::Sixteen);
+    png_encoder.set_color(png::ColorType::RGBA);
+
+    let mut png_writer = png_encoder.write_header()?;
+    write_png_color_space(&mut png_writer, image.color_space)?;
+    // PNG samples are big-endian.
+    let data: Vec<u8> = image
+        .data
+        .iter()
+        .flat_map(|value| value.to_be_bytes().to_vec())
+        .collect();
+    png_writer.write_image_data(&data)?;
+    drop(png_writer);
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Encodes `depth` as `format` into `writer`: a 16-bit grayscale PNG, an EXR with a float `Z`
+/// channel or raw native-endian floats.
+///
+/// Other formats fail with [`RenderError::InvalidConfig`]; see [`OutputFormat::stores_depth`].
+pub fn write_depth_image<W: Write>(
+    depth: &DepthImage,
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<()> {
+    let (width, height) = (depth.width, depth.height);
+
+    match format {
+        OutputFormat::Png => {
+            let mut png_encoder = png::Encoder::new(&mut writer, width, height);
+
+            png_encoder.set_depth(png::BitDepth::Sixteen);
+            png_encoder.set_color(png::ColorType::Grayscale);
+
+            let mut png_writer = png_encoder.write_header()?;
+            write_png_color_space(&mut png_writer, ColorSpace::Linear)?;
+            let data: Vec<u8> = depth
+                .to_unorm16()
+                .iter()
+                .flat_map(|value| value.to_be_bytes().to_vec())
+                .collect();
+            png_writer.write_image_data(&data)?;
+        }
+        OutputFormat::Exr => exr::encode(&mut writer, exr::DEPTH, &depth.data, width, height)?,
+        OutputFormat::Raw => {
+            let data: Vec<u8> = depth
+                .data
+                .iter()
+                .flat_map(|value| value.to_ne_bytes().to_vec())
+                .collect();
+            writer.write_all(&data)?;
+        }
+        _ => {
+            return Err(RenderError::InvalidConfig(format!(
+                "{} cannot store depth",
+                format
+            )))
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Encodes `ids` as `format` into `writer`: raw output keeps the IDs as native-endian `u32`s, the
+/// other formats get them color-coded with [`id_color`].
+pub fn write_id_image<W: Write>(ids: &IdImage, format: OutputFormat, mut writer: W) -> Result<()> {
+    if format != OutputFormat::Raw {
+        return write_image(&ids.to_rgba_image(), format, writer);
+    }
+
+    let data: Vec<u8> = ids
+        .data
+        .iter()
+        .flat_map(|id| id.to_ne_bytes().to_vec())
+        .collect();
+    writer.write_all(&data)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes a JSON legend for a color-coded ID image: every ID present with its color and the
+/// number of pixels it covers.
+///
+/// ```json
+/// {"width": 800, "height": 600, "ids": [{"id": 1, "color": "#4d81ff", "pixels": 120000}]}
+/// ```
+pub fn write_id_legend<W: Write>(ids: &IdImage, mut writer: W) -> Result<()> {
+    let entries: Vec<Value> = ids
+        .id_counts()
+        .into_iter()
+        .map(|(id, pixels)| {
+            let [r, g, b] = id_color(id);
+            json!({
+                "id": id,
+                "color": format!("#{:02x}{:02x}{:02x}", r, g, b),
+                "pixels": pixels,
+            })
+        })
+        .collect();
+    let legend = json!({
+        "width": ids.width,
+        "height": ids.height,
+        "ids": entries,
+    });
+
+    writeln!(writer, "{:#}", legend)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the chunks telling viewers how to decode the pixels: `sRGB` with the matching fallback
+/// `gAMA` for sRGB, a gamma of 1.0 for linear values.
+fn write_png_color_space<W: Write>(
+    png_writer: &mut png::Writer<W>,
+    color_space: ColorSpace,
+) -> Result<()> {
+    match color_space {
+        ColorSpace::Srgb => {
+            png_writer.write_chunk(*b"sRGB", &[PNG_SRGB_PERCEPTUAL])?;
+            png_writer.write_chunk(*b"gAMA", &PNG_GAMMA_SRGB.to_be_bytes())?;
+        }
+        ColorSpace::Linear => {
+            png_writer.write_chunk(*b"gAMA", &PNG_GAMMA_LINEAR.to_be_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Encodes float `image` as `format` into `writer`, like [`write_image`].
+///
+/// Other formats get the values clamped to `0.0..=1.0`, with 16 bits per channel for PNG.
+pub fn write_hdr_image<W: Write>(
+    image: &RgbaF32Image,
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<()> {
+    let (width, height) = (image.width, image.height);
+
+    match format {
+        OutputFormat::Exr => exr::encode(&mut writer, exr::RGBA, &image.data, width, height)?,
+        OutputFormat::Hdr => {
+            let pixels: Vec<Rgb<f32>> = image
+                .data
+                .chunks_exact(4)
+                .map(|pixel| Rgb([pixel[0], pixel[1], pixel[2]]))
+                .collect();
+            HdrEncoder::new(&mut writer).encode(&pixels, width as usize, height as usize)?;
+        }
+        OutputFormat::Png => return write_image16(&image.to_rgba16_image(), format, writer),
+        _ => return write_image(&image.to_rgba_image(), format, writer),
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// The pixels of `image` without alpha.
+fn rgb(image: &RgbaImage) -> Vec<u8> {
+    image
+        .data
+        .chunks_exact(4)
+        .flat_map(|pixel| pixel[..3].iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    /// The type and data of every chunk after the PNG signature.
+    fn png_chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let chunk_type = rest[4..8].try_into().unwrap();
+            chunks.push((chunk_type, rest[8..8 + len].to_vec()));
+            // Skip the CRC.
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    fn color_chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        png_chunks(png)
+            .into_iter()
+            .filter(|(chunk_type, _)| chunk_type == b"sRGB" || chunk_type == b"gAMA")
+            .collect()
+    }
+
+    /// The bit depth and samples of a PNG, without the decoder's default 16-bit stripping.
+    fn png_pixels(png: &[u8]) -> (png::BitDepth, Vec<u8>) {
+        let mut decoder = png::Decoder::new(png);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        (info.bit_depth, pixels)
+    }
+
+    fn image(color_space: ColorSpace) -> RgbaImage {
+        RgbaImage {
+            width: 2,
+            height: 2,
+            data: vec![
+                0, 0, 0, 255, 64, 64, 64, 64, 128, 0, 255, 128, 255, 255, 255, 0,
+            ],
+            color_space,
+        }
+    }
+
+    #[test]
+    fn parses_names_and_extensions() {
+        let jpeg = OutputFormat::Jpeg {
+            quality: DEFAULT_JPEG_QUALITY,
+        };
+        assert_eq!("png".parse(), Ok(OutputFormat::Png));
+        assert_eq!("jpeg".parse(), Ok(jpeg));
+        assert_eq!("jpg".parse(), Ok(jpeg));
+        assert_eq!("rgba".parse(), Ok(OutputFormat::Raw));
+        assert_eq!("bmp".parse::<OutputFormat>(), Err(()));
+        for name in OutputFormat::NAMES {
+            let format: OutputFormat = name.parse().unwrap();
+            assert_eq!(format.extension().parse(), Ok(format));
+        }
+
+        let from_extension = |path: &str| OutputFormat::from_extension(Path::new(path));
+        assert_eq!(from_extension("out.PNG"), Some(OutputFormat::Png));
+        assert_eq!(from_extension("photo.Jpg"), Some(jpeg));
+        assert_eq!(from_extension("frame.rgba"), Some(OutputFormat::Raw));
+        assert_eq!(from_extension("dir.exr/frame.HDR"), Some(OutputFormat::Hdr));
+        assert_eq!(from_extension("out.bmp"), None);
+        assert_eq!(from_extension("out"), None);
+    }
+
+    #[test]
+    fn png_is_srgb() {
+        let srgb_chunks = vec![
+            (*b"sRGB", vec![PNG_SRGB_PERCEPTUAL]),
+            (*b"gAMA", PNG_GAMMA_SRGB.to_be_bytes().to_vec()),
+        ];
+
+        for &color_space in &[ColorSpace::Linear, ColorSpace::Srgb] {
+            let image = image(color_space);
+            let mut png = Vec::new();
+            write_image(&image, OutputFormat::Png, &mut png).unwrap();
+
+            assert_eq!(color_chunks(&png), srgb_chunks);
+            assert_eq!(
+                png_pixels(&png),
+                (png::BitDepth::Eight, image.to_srgb().data)
+            );
+        }
+    }
+
+    #[test]
+    fn png16_keeps_linear_values() {
+        let image = Rgba16Image {
+            width: 1,
+            height: 1,
+            data: vec![0, 0x1234, 0xabcd, 0xffff],
+            color_space: ColorSpace::Linear,
+        };
+        let mut png = Vec::new();
+        write_image16(&image, OutputFormat::Png, &mut png).unwrap();
+
+        assert_eq!(
+            color_chunks(&png),
+            [(*b"gAMA", PNG_GAMMA_LINEAR.to_be_bytes().to_vec())]
+        );
+        assert_eq!(
+            png_pixels(&png),
+            (
+                png::BitDepth::Sixteen,
+                vec![0x00, 0x00, 0x12, 0x34, 0xab, 0xcd, 0xff, 0xff]
+            )
+        );
+    }
+}
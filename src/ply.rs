@@ -0,0 +1,614 @@
+//! ASCII and binary PLY parsing.
+
+use std::{convert::TryInto, str::SplitAsciiWhitespace};
+
+use crate::{
+    geometry::MeshVertex,
+    image::srgb_to_linear,
+    mesh::{Mesh, DEFAULT_VERTEX_COLOR},
+};
+
+/// Parses the `vertex` and `face` elements of a PLY file into a mesh, triangulating faces as fans
+/// and generating normals if vertices have none. Other elements are skipped.
+///
+/// Vertices may have `x`, `y`, `z`, `nx`, `ny`, `nz`, texture coordinates as `u`/`v`, `s`/`t` or
+/// `texture_u`/`texture_v`, and `red`, `green`, `blue` and `alpha`. Integer colors are taken as
+/// sRGB, float colors as linear.
+pub fn parse(data: &[u8]) -> Result<Mesh, String> {
+    let (header, body) = split_header(data)?;
+    let header = parse_header(header)?;
+
+    let mut reader = match header.format {
+        Format::Ascii => {
+            let text = std::str::from_utf8(body)
+                .map_err(|_| "ASCII PLY data is not valid UTF-8".to_string())?;
+            Reader::Ascii(text.split_ascii_whitespace())
+        }
+        Format::BinaryLittleEndian => Reader::Binary {
+            data: body,
+            big_endian: false,
+        },
+        Format::BinaryBigEndian => Reader::Binary {
+            data: body,
+            big_endian: true,
+        },
+    };
+
+    let mut mesh = Mesh::default();
+    let mut has_normals = false;
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => {
+                has_normals = element
+                    .properties
+                    .iter()
+                    .any(|property| vertex_slot(&property.name) == Slot::Normal(0));
+                mesh.vertices = read_vertices(&mut reader, element)?;
+            }
+            "face" => mesh.indices = read_faces(&mut reader, element)?,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        reader.read_property(property)?;
+                    }
+                }
+            }
+        }
+    }
+
+    // Indices are only trusted after this.
+    mesh.validate()?;
+    if !has_normals {
+        mesh.generate_normals();
+    }
+
+    Ok(mesh)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return Err(format!("unknown PLY property type '{}'", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// The value range integer colors are normalized by.
+    fn max_color(self) -> Option<f64> {
+        match self {
+            ScalarType::U8 => Some(u8::MAX.into()),
+            ScalarType::U16 => Some(u16::MAX.into()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    /// The type of the item count, then of each item.
+    List(ScalarType, ScalarType),
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    ty: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug)]
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+/// Splits `data` after the line holding `end_header`.
+fn split_header(data: &[u8]) -> Result<(&str, &[u8]), String> {
+    if !data.starts_with(b"ply") {
+        return Err("PLY file does not start with 'ply'".to_string());
+    }
+
+    let marker = b"end_header";
+    let end = data
+        .windows(marker.len())
+        .position(|window| window == marker)
+        .ok_or_else(|| "PLY header has no end_header".to_string())?
+        + marker.len();
+    let body_start = match data[end..].iter().position(|&byte| byte == b'\n') {
+        Some(newline) => end + newline + 1,
+        None => data.len(),
+    };
+
+    let header = std::str::from_utf8(&data[..end])
+        .map_err(|_| "PLY header is not valid UTF-8".to_string())?;
+
+    Ok((header, &data[body_start..]))
+}
+
+fn parse_header(header: &str) -> Result<Header, String> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for line in header.lines().skip(1) {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(format!("unknown PLY format '{}'", name)),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid count '{}' of PLY element {}", count, name))?,
+                properties: Vec::new(),
+            }),
+            ["property", ty @ .., name] => {
+                let ty = match ty {
+                    [ty] => PropertyType::Scalar(ScalarType::parse(ty)?),
+                    ["list", count, item] => {
+                        PropertyType::List(ScalarType::parse(count)?, ScalarType::parse(item)?)
+                    }
+                    _ => return Err(format!("invalid PLY property '{}'", line)),
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| format!("PLY property '{}' outside of an element", name))?
+                    .properties
+                    .push(Property {
+                        name: name.to_string(),
+                        ty,
+                    });
+            }
+            ["comment", ..] | ["obj_info", ..] | ["end_header"] | [] => {}
+            _ => return Err(format!("invalid PLY header line '{}'", line)),
+        }
+    }
+
+    Ok(Header {
+        format: format.ok_or_else(|| "PLY header has no format".to_string())?,
+        elements,
+    })
+}
+
+/// Where a vertex property goes in [`MeshVertex`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Position(usize),
+    Normal(usize),
+    Uv(usize),
+    Color(usize),
+    Ignored,
+}
+
+fn vertex_slot(name: &str) -> Slot {
+    match name {
+        "x" => Slot::Position(0),
+        "y" => Slot::Position(1),
+        "z" => Slot::Position(2),
+        "nx" => Slot::Normal(0),
+        "ny" => Slot::Normal(1),
+        "nz" => Slot::Normal(2),
+        "u" | "s" | "texture_u" | "texture_s" => Slot::Uv(0),
+        "v" | "t" | "texture_v" | "texture_t" => Slot::Uv(1),
+        "red" => Slot::Color(0),
+        "green" => Slot::Color(1),
+        "blue" => Slot::Color(2),
+        "alpha" => Slot::Color(3),
+        _ => Slot::Ignored,
+    }
+}
+
+fn read_vertices(reader: &mut Reader, element: &Element) -> Result<Vec<MeshVertex>, String> {
+    let slots: Vec<Slot> = element
+        .properties
+        .iter()
+        .map(|property| vertex_slot(&property.name))
+        .collect();
+
+    // The count comes from the header, so let the body decide how much memory it takes.
+    let mut vertices = Vec::new();
+    for _ in 0..element.count {
+        let mut vertex = MeshVertex {
+            color: DEFAULT_VERTEX_COLOR,
+            ..MeshVertex::default()
+        };
+
+        for (property, &slot) in element.properties.iter().zip(&slots) {
+            let ty = match property.ty {
+                PropertyType::Scalar(ty) if slot != Slot::Ignored => ty,
+                _ => {
+                    reader.read_property(property)?;
+                    continue;
+                }
+            };
+            let value = reader.read(ty)?;
+
+            match slot {
+                Slot::Position(axis) => vertex.position[axis] = value as f32,
+                Slot::Normal(axis) => vertex.normal[axis] = value as f32,
+                // PLY puts the origin of texture space at the bottom left, Vulkan at the top left.
+                Slot::Uv(0) => vertex.uv[0] = value as f32,
+                Slot::Uv(_) => vertex.uv[1] = 1.0 - value as f32,
+                Slot::Color(channel) => {
+                    vertex.color[channel] = match ty.max_color() {
+                        Some(max) if channel < 3 => srgb_to_linear((value / max) as f32),
+                        Some(max) => (value / max) as f32,
+                        None => value as f32,
+                    }
+                }
+                Slot::Ignored => unreachable!(),
+            }
+        }
+
+        vertices.push(vertex);
+    }
+
+    Ok(vertices)
+}
+
+fn read_faces(reader: &mut Reader, element: &Element) -> Result<Vec<u32>, String> {
+    let mut indices = Vec::new();
+    let mut face = Vec::new();
+
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property.ty {
+                PropertyType::List(count_type, item_type)
+                    if property.name == "vertex_indices" || property.name == "vertex_index" =>
+                {
+                    let count = reader.read(count_type)? as usize;
+                    face.clear();
+                    for _ in 0..count {
+                        let index = reader.read(item_type)?;
+                        if index < 0.0 || index.fract() != 0.0 || index > f64::from(u32::MAX) {
+                            return Err(format!("invalid vertex index {} in PLY face", index));
+                        }
+                        face.push(index as u32);
+                    }
+
+                    for i in 1..face.len().saturating_sub(1) {
+                        indices.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                    }
+                }
+                _ => reader.read_property(property)?,
+            }
+        }
+    }
+
+    Ok(indices)
+}
+
+/// Reads values of the body in header order.
+enum Reader<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Reader<'_> {
+    /// Reads a value of any type, exactly for every integer type.
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        match self {
+            Reader::Ascii(tokens) => {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| "PLY data ends early".to_string())?;
+                token
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}' in PLY data", token))
+            }
+            Reader::Binary { data, big_endian } => {
+                if data.len() < ty.size() {
+                    return Err("PLY data ends early".to_string());
+                }
+                let (bytes, rest) = data.split_at(ty.size());
+                *data = rest;
+
+                macro_rules! from_bytes {
+                    ($ty:ty) => {{
+                        let bytes = bytes.try_into().unwrap();
+                        f64::from(if *big_endian {
+                            <$ty>::from_be_bytes(bytes)
+                        } else {
+                            <$ty>::from_le_bytes(bytes)
+                        })
+                    }};
+                }
+                Ok(match ty {
+                    ScalarType::I8 => from_bytes!(i8),
+                    ScalarType::U8 => from_bytes!(u8),
+                    ScalarType::I16 => from_bytes!(i16),
+                    ScalarType::U16 => from_bytes!(u16),
+                    ScalarType::I32 => from_bytes!(i32),
+                    ScalarType::U32 => from_bytes!(u32),
+                    ScalarType::F32 => from_bytes!(f32),
+                    ScalarType::F64 => from_bytes!(f64),
+                })
+            }
+        }
+    }
+
+    /// Reads and discards a property.
+    fn read_property(&mut self, property: &Property) -> Result<(), String> {
+        match property.ty {
+            PropertyType::Scalar(ty) => {
+                self.read(ty)?;
+            }
+            PropertyType::List(count_type, item_type) => {
+                let count = self.read(count_type)? as usize;
+                for _ in 0..count {
+                    self.read(item_type)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A quad with sRGB byte colors, a skipped texture coordinate list on its face and a skipped
+    /// `edge` element.
+    const HEADER: &str = "element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+property list uchar float texcoord
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+";
+
+    const POSITIONS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+    const COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    fn ply(format: &str, body: &[u8]) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\ncomment test\n{}", format, HEADER).into_bytes();
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn binary_body(big_endian: bool) -> Vec<u8> {
+        let mut body = Vec::new();
+        let float = |body: &mut Vec<u8>, value: f32| {
+            body.extend_from_slice(&if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            })
+        };
+        let int = |body: &mut Vec<u8>, value: i32| {
+            body.extend_from_slice(&if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            })
+        };
+
+        for (position, color) in POSITIONS.iter().zip(&COLORS) {
+            for &value in position {
+                float(&mut body, value);
+            }
+            body.extend_from_slice(color);
+        }
+        body.push(4);
+        for index in 0..4 {
+            int(&mut body, index);
+        }
+        body.push(2);
+        float(&mut body, 0.5);
+        float(&mut body, 0.5);
+        int(&mut body, 0);
+        int(&mut body, 1);
+        body
+    }
+
+    fn expected_quad() -> Mesh {
+        let vertices = POSITIONS
+            .iter()
+            .zip(&COLORS)
+            .map(|(&position, color)| {
+                let channel = |i: usize| srgb_to_linear(f32::from(color[i]) / 255.0);
+                MeshVertex {
+                    position,
+                    normal: [0.0, 0.0, 1.0],
+                    uv: [0.0; 2],
+                    color: [channel(0), channel(1), channel(2), 1.0],
+                }
+            })
+            .collect();
+
+        Mesh {
+            vertices,
+            indices: vec![0, 1, 2, 0, 2, 3],
+        }
+    }
+
+    #[test]
+    fn ascii() {
+        let body = "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n\
+                    4 0 1 2 3 2 0.5 0.5\n0 1\n";
+
+        assert_eq!(parse(&ply("ascii", body.as_bytes())), Ok(expected_quad()));
+    }
+
+    #[test]
+    fn binary() {
+        assert_eq!(
+            parse(&ply("binary_little_endian", &binary_body(false))),
+            Ok(expected_quad())
+        );
+        assert_eq!(
+            parse(&ply("binary_big_endian", &binary_body(true))),
+            Ok(expected_quad())
+        );
+    }
+
+    #[test]
+    fn float_colors_and_normals() {
+        let data = "ply
+format ascii 1.0
+element vertex 3
+property double x
+property double y
+property double z
+property float nx
+property float ny
+property float nz
+property float s
+property float t
+property float red
+property float green
+property float blue
+property float alpha
+element face 1
+property list uchar uint vertex_index
+end_header
+0 0 0 0 0 -1 0 0 0.5 0.25 0 0.5
+1 0 0 0 0 -1 1 0 0.5 0.25 0 0.5
+0 1 0 0 0 -1 0 1 0.5 0.25 0 0.5
+3 0 1 2
+";
+        let mesh = parse(data.as_bytes()).unwrap();
+
+        // Float colors are linear already, and the file's normals are kept.
+        assert_eq!(
+            mesh.vertices[2],
+            MeshVertex {
+                position: [0.0, 1.0, 0.0],
+                normal: [0.0, 0.0, -1.0],
+                uv: [0.0, 0.0],
+                color: [0.5, 0.25, 0.0, 0.5],
+            }
+        );
+        assert_eq!(mesh.indices, [0, 1, 2]);
+    }
+
+    fn triangle_with_face(face: &str) -> Result<Mesh, String> {
+        let data = format!(
+            "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0
+1 0 0
+0 1 0
+{}
+",
+            face
+        );
+        parse(data.as_bytes())
+    }
+
+    #[test]
+    fn invalid_indices() {
+        assert!(triangle_with_face("3 0 1 2").is_ok());
+        assert_eq!(
+            triangle_with_face("3 0 1 -1"),
+            Err("invalid vertex index -1 in PLY face".to_string())
+        );
+        assert_eq!(
+            triangle_with_face("3 0 1 1.5"),
+            Err("invalid vertex index 1.5 in PLY face".to_string())
+        );
+        assert_eq!(
+            triangle_with_face("3 0 1 3"),
+            Err("vertex index 3 is out of range for 3 vertices".to_string())
+        );
+    }
+
+    #[test]
+    fn counts_beyond_the_body() {
+        let data = "ply
+format binary_little_endian 1.0
+element vertex 4000000000
+property float x
+end_header
+";
+        assert_eq!(
+            parse(data.as_bytes()),
+            Err("PLY data ends early".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(parse(b"obj").is_err());
+        assert_eq!(
+            parse(b"ply\nformat ascii 1.0\n"),
+            Err("PLY header has no end_header".to_string())
+        );
+        assert_eq!(
+            parse(b"ply\nelement vertex 0\nend_header\n"),
+            Err("PLY header has no format".to_string())
+        );
+        assert_eq!(
+            parse(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n"),
+            Err("PLY property 'x' outside of an element".to_string())
+        );
+    }
+}
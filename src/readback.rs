@@ -0,0 +1,259 @@
+//! Copying a rendered image into host memory.
+
+use std::{str::FromStr, sync::Arc};
+
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    error::Result,
+    memory::{invalidate_mapped, MemoryRequest},
+    resource::{Buffer, Device, Image},
+};
+
+/// How an attachment is copied to host-visible memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadbackMethod {
+    /// `vkCmdCopyImageToBuffer` into a staging buffer with tightly packed rows.
+    Buffer,
+    /// `vkCmdCopyImage` into a `LINEAR`-tiled image, which needs `TRANSFER_DST` support for the
+    /// format with linear tiling.
+    LinearImage,
+}
+
+impl Default for ReadbackMethod {
+    fn default() -> Self {
+        ReadbackMethod::Buffer
+    }
+}
+
+impl FromStr for ReadbackMethod {
+    type Err = ();
+
+    /// Parses `buffer` or `linear-image`.
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s {
+            "buffer" => Ok(ReadbackMethod::Buffer),
+            "linear-image" => Ok(ReadbackMethod::LinearImage),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Host-visible copy destination for one attachment, created once and reused every frame.
+pub(crate) enum Readback {
+    Buffer(Buffer),
+    LinearImage(Image),
+}
+
+impl Readback {
+    pub fn new(
+        device: &Arc<Device>,
+        method: ReadbackMethod,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        texel_size: u32,
+    ) -> Result<Self> {
+        let readback = match method {
+            ReadbackMethod::Buffer => {
+                let buffer_create_info = vk::BufferCreateInfo::builder()
+                    .size(
+                        vk::DeviceSize::from(extent.width)
+                            * vk::DeviceSize::from(extent.height)
+                            * vk::DeviceSize::from(texel_size),
+                    )
+                    .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+                let buffer = Buffer::new(device, &buffer_create_info, MemoryRequest::READBACK)?;
+                buffer.set_name("readback buffer")?;
+                Readback::Buffer(buffer)
+            }
+            ReadbackMethod::LinearImage => {
+                let image_create_info = vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format)
+                    .extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::LINEAR)
+                    .usage(vk::ImageUsageFlags::TRANSFER_DST);
+
+                let image = Image::new(device, &image_create_info, MemoryRequest::READBACK)?;
+                image.set_name("readback image")?;
+                Readback::LinearImage(image)
+            }
+        };
+
+        Ok(readback)
+    }
+
+    pub fn set_name(&self, name: &str) -> VkResult<()> {
+        match self {
+            Readback::Buffer(buffer) => buffer.set_name(name),
+            Readback::LinearImage(image) => image.set_name(name),
+        }
+    }
+
+    /// Records the copy of the `aspect` of `src_image`, which must be in `TRANSFER_SRC_OPTIMAL`
+    /// layout, followed by a barrier making the result visible to the host.
+    pub fn record_copy(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        src_image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        extent: vk::Extent2D,
+    ) {
+        let subresource_layers = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(aspect)
+            .layer_count(1)
+            .build();
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(aspect)
+            .level_count(1)
+            .layer_count(1)
+            .build();
+        let extent = vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        };
+
+        match self {
+            Readback::Buffer(buffer) => {
+                // Zero row length and image height mean tightly packed rows.
+                let copy_region = vk::BufferImageCopy::builder()
+                    .image_subresource(subresource_layers)
+                    .image_extent(extent)
+                    .build();
+
+                let buffer_barrier = vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::HOST_READ)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(buffer.handle())
+                    .size(vk::WHOLE_SIZE)
+                    .build();
+
+                unsafe {
+                    device.cmd_copy_image_to_buffer(
+                        command_buffer,
+                        src_image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        buffer.handle(),
+                        &[copy_region],
+                    );
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::HOST,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[buffer_barrier],
+                        &[],
+                    );
+                }
+            }
+            Readback::LinearImage(image) => {
+                let to_transfer_dst = vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::empty())
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .image(image.handle())
+                    .subresource_range(subresource_range)
+                    .build();
+
+                let copy_region = vk::ImageCopy::builder()
+                    .src_subresource(subresource_layers)
+                    .dst_subresource(subresource_layers)
+                    .extent(extent)
+                    .build();
+
+                let to_general = vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::HOST_READ)
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .image(image.handle())
+                    .subresource_range(subresource_range)
+                    .build();
+
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[to_transfer_dst],
+                    );
+                    device.cmd_copy_image(
+                        command_buffer,
+                        src_image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        image.handle(),
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &[copy_region],
+                    );
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::HOST,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[to_general],
+                    );
+                }
+            }
+        }
+    }
+
+    /// Mapped bytes of the last completed readback, and the distance between rows in them.
+    pub fn mapped(
+        &self,
+        device: &Device,
+        extent: vk::Extent2D,
+        texel_size: u32,
+    ) -> Result<(&[u8], usize)> {
+        let row_size = (extent.width * texel_size) as usize;
+
+        let (allocation, offset, row_pitch) = match self {
+            Readback::Buffer(buffer) => (buffer.allocation(), 0, row_size),
+            Readback::LinearImage(image) => {
+                let subresource = vk::ImageSubresource::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .build();
+                let layout =
+                    unsafe { device.get_image_subresource_layout(image.handle(), subresource) };
+
+                (
+                    image.allocation(),
+                    layout.offset as usize,
+                    layout.row_pitch as usize,
+                )
+            }
+        };
+
+        invalidate_mapped(device, allocation)?;
+
+        let data = allocation
+            .mapped_ptr()
+            .expect("readback memory is host visible");
+        let len = row_pitch * (extent.height as usize - 1) + row_size;
+
+        Ok((
+            unsafe { std::slice::from_raw_parts(data.add(offset), len) },
+            row_pitch,
+        ))
+    }
+}
@@ -0,0 +1,1300 @@
+use std::{
+    ffi::CString,
+    fs::File,
+    io::Cursor,
+    ptr::{self, null},
+    sync::Arc,
+};
+
+use ash::{prelude::VkResult, util::read_spv, vk};
+
+use crate::{
+    allocator::AllocatorStats,
+    config::{RenderConfig, ID_ENTRY_POINT, ID_FORMAT, SUPPORTED_COLOR_FORMATS},
+    debug::{ValidationMessage, ValidationSink},
+    depth::{self, select_depth_format, DepthConfig},
+    device::{enumerate_physical_devices, select_physical_device, DeviceSelection, DeviceSelector},
+    error::{RenderError, Result},
+    geometry::{Geometry, GeometryBuffers},
+    image::{DepthImage, MappedImage, Rgba16Image, RgbaF32Image, RgbaImage},
+    instance::create_instance,
+    memory::MemoryRequest,
+    multisample::select_sample_count,
+    readback::{Readback, ReadbackMethod},
+    resource::{
+        CommandPool, Device, Fence, Framebuffer, Image, ImageView, Pipeline, PipelineLayout,
+        RenderPass, ShaderModule,
+    },
+};
+
+const BUILTIN_SHADER: &[u8] = include_bytes!(env!("shader.spv"));
+
+/// Owns every Vulkan object needed to render the triangle into an offscreen image.
+///
+/// Construct it once and call [`OffscreenRenderer::render`] as often as needed; the command
+/// buffers are recorded up front and only resubmitted.
+///
+/// Fields are declared in destruction order: every handle is dropped before the objects it was
+/// created from.
+pub struct OffscreenRenderer {
+    graphics_queue: vk::Queue,
+    command_buffer: vk::CommandBuffer,
+    copy_cmd: vk::CommandBuffer,
+    fence: Fence,
+    _command_pool: CommandPool,
+    _framebuffer: Framebuffer,
+    _graphics_pipeline: Pipeline,
+    _pipeline_layout: PipelineLayout,
+    _render_pass: RenderPass,
+    color_attachments: Vec<ColorAttachment>,
+    depth: Option<DepthAttachment>,
+    _geometry: Option<GeometryBuffers>,
+    device: Arc<Device>,
+    device_selection: DeviceSelection,
+    config: RenderConfig,
+}
+
+impl OffscreenRenderer {
+    pub fn new(mut config: RenderConfig) -> Result<Self> {
+        let extent = config.extent();
+
+        if extent.width == 0 || extent.height == 0 {
+            return Err(RenderError::InvalidConfig(format!(
+                "extent {}x{} must not be empty",
+                extent.width, extent.height
+            )));
+        }
+        // Only extra attachments can hold IDs; the first one is always an image.
+        let is_supported = |format| {
+            SUPPORTED_COLOR_FORMATS
+                .iter()
+                .any(|&(_, supported)| supported == format)
+        };
+        if !is_supported(config.color_format) {
+            return Err(RenderError::UnsupportedFormat(config.color_format));
+        }
+        for attachment in &config.extra_color_attachments {
+            if attachment.format != ID_FORMAT && !is_supported(attachment.format) {
+                return Err(RenderError::UnsupportedFormat(attachment.format));
+            }
+        }
+        if let Some(fraction) = config.multisample.min_sample_shading {
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(RenderError::InvalidConfig(format!(
+                    "minimum sample shading {} is outside 0.0..=1.0",
+                    fraction
+                )));
+            }
+        }
+        if let Some(geometry) = &config.geometry {
+            geometry.validate()?;
+        }
+
+        let instance = create_instance(config.validation, &config.debug_messenger)?;
+
+        let device_selection = {
+            let selector = config.device.clone().or_else(DeviceSelector::from_env);
+            let mut requirements = config.device_requirements.clone();
+            config.multisample.add_requirements(&mut requirements);
+
+            select_physical_device(
+                enumerate_physical_devices(&instance)?,
+                selector.as_ref(),
+                &requirements,
+            )
+            .map_err(RenderError::NoSuitableDevice)?
+        };
+        let physical_device = device_selection.device.handle;
+        let queue_family_index = device_selection.queue_family_index;
+
+        // 8-bit output is sRGB-encoded when it is saved either way, UNORM just bands more.
+        if config.color_format == vk::Format::R8G8B8A8_SRGB
+            && !supports_color_target(
+                &instance,
+                physical_device,
+                config.color_format,
+                config.readback,
+            )
+        {
+            log::warn!("R8G8B8A8_SRGB is not supported, rendering to R8G8B8A8_UNORM instead");
+            config.color_format = vk::Format::R8G8B8A8_UNORM;
+        }
+        check_render_target_support(&instance, physical_device, &config)?;
+        let depth_format = config
+            .depth
+            .as_ref()
+            .map(|depth| select_depth_format(&instance, physical_device, depth))
+            .transpose()?;
+        let samples = select_sample_count(
+            &instance,
+            physical_device,
+            &config.multisample,
+            &config.color_formats(),
+            depth_format,
+        );
+        // Multisampled images cannot be copied to a buffer, and resolving depth needs Vulkan 1.2.
+        // The requested count may have been lowered to one sample, which reads back fine.
+        if samples != vk::SampleCountFlags::TYPE_1
+            && matches!(&config.depth, Some(depth) if depth.readback)
+        {
+            return Err(RenderError::InvalidConfig(
+                "depth readback needs a single-sampled depth attachment".to_string(),
+            ));
+        }
+
+        let device = {
+            let queue_create_info = vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(queue_family_index)
+                .queue_priorities(&[1.0])
+                .build();
+
+            let device = device_selection.capabilities.create_device(
+                &instance,
+                physical_device,
+                &[queue_create_info],
+            )?;
+
+            Arc::new(Device::new(
+                instance,
+                physical_device,
+                device_selection.capabilities.clone(),
+                device,
+            ))
+        };
+
+        let graphics_queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+        device.set_object_name(graphics_queue, "graphics queue")?;
+
+        let pipeline_layout = {
+            // Geometry comes with a transform for the vertex shader.
+            let push_constant_ranges: Vec<_> = config
+                .geometry
+                .iter()
+                .map(|_| Geometry::push_constant_range())
+                .collect();
+            let pipeline_layout_create_info =
+                vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&push_constant_ranges);
+
+            let pipeline_layout =
+                unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None) }?;
+
+            PipelineLayout::from_raw(&device, pipeline_layout)
+        };
+        pipeline_layout.set_name("triangle pipeline layout")?;
+
+        let color_attachments = std::iter::once(("color", config.color_format))
+            .chain(
+                config
+                    .extra_color_attachments
+                    .iter()
+                    .map(|attachment| (attachment.name.as_str(), attachment.format)),
+            )
+            .map(|(name, format)| {
+                ColorAttachment::new(&device, name, format, samples, config.readback, extent)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let color_attachment_count = color_attachments.len() as u32;
+
+        let depth = match (&config.depth, depth_format) {
+            (Some(depth_config), Some(format)) => Some(DepthAttachment::new(
+                &device,
+                format,
+                samples,
+                depth_config,
+                extent,
+            )?),
+            _ => None,
+        };
+
+        // render pass
+
+        let render_pass = {
+            // With multisampling the subpass renders into the multisampled images and resolves
+            // them into the attachments that are read back, which come after the depth attachment.
+            let mut attachments: Vec<vk::AttachmentDescription> = color_attachments
+                .iter()
+                .map(ColorAttachment::render_target_description)
+                .collect();
+
+            let color_attachment_refs: Vec<vk::AttachmentReference> = (0..color_attachment_count)
+                .map(|attachment| vk::AttachmentReference {
+                    attachment,
+                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                })
+                .collect();
+            let depth_attachment_ref = vk::AttachmentReference {
+                attachment: color_attachment_count,
+                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            };
+
+            let mut subpass = vk::SubpassDescription::builder()
+                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                .color_attachments(&color_attachment_refs);
+            if let Some(depth) = &depth {
+                attachments.push(depth.description());
+                subpass = subpass.depth_stencil_attachment(&depth_attachment_ref);
+            }
+            let first_resolve_attachment = attachments.len() as u32;
+            let resolve_attachment_refs: Vec<vk::AttachmentReference> = (0..color_attachment_count)
+                .map(|i| vk::AttachmentReference {
+                    attachment: first_resolve_attachment + i,
+                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                })
+                .collect();
+            if samples != vk::SampleCountFlags::TYPE_1 {
+                attachments.extend(color_attachments.iter().map(ColorAttachment::description));
+                subpass = subpass.resolve_attachments(&resolve_attachment_refs);
+            }
+            let subpasses = [subpass.build()];
+
+            // Order the attachment writes against the readback copies of the previous and the
+            // current frame.
+            let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+            let attachment_writes = vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+            let dependencies = [
+                vk::SubpassDependency::builder()
+                    .src_subpass(vk::SUBPASS_EXTERNAL)
+                    .dst_subpass(0)
+                    .src_stage_mask(vk::PipelineStageFlags::TRANSFER)
+                    .dst_stage_mask(attachment_stages)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+                    .dst_access_mask(attachment_writes)
+                    .build(),
+                vk::SubpassDependency::builder()
+                    .src_subpass(0)
+                    .dst_subpass(vk::SUBPASS_EXTERNAL)
+                    .src_stage_mask(attachment_stages)
+                    .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+                    .src_access_mask(attachment_writes)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                    .build(),
+            ];
+
+            let renderpass_create_info = vk::RenderPassCreateInfo::builder()
+                .attachments(&attachments)
+                .subpasses(&subpasses)
+                .dependencies(&dependencies)
+                .build();
+
+            let render_pass = unsafe { device.create_render_pass(&renderpass_create_info, None) }?;
+
+            RenderPass::from_raw(&device, render_pass)
+        };
+        render_pass.set_name("triangle render pass")?;
+
+        let graphics_pipeline = {
+            let code = match &config.shader {
+                Some(path) => read_spv(&mut File::open(path)?)?,
+                None => read_spv(&mut Cursor::new(BUILTIN_SHADER))?,
+            };
+
+            let shader_module = ShaderModule::from_raw(&device, unsafe {
+                create_shader_module(&device, &code).map_err(RenderError::ShaderModule)?
+            });
+            shader_module.set_name(match &config.shader {
+                Some(path) => path.to_str().unwrap_or("custom shader"),
+                None => "built-in shader",
+            })?;
+
+            let main_vs = CString::new(config.vertex_entry_point.as_str()).map_err(|_| {
+                RenderError::InvalidConfig(format!(
+                    "invalid vertex entry point {:?}",
+                    config.vertex_entry_point
+                ))
+            })?;
+            let main_fs = CString::new(config.fragment_entry_point.as_str()).map_err(|_| {
+                RenderError::InvalidConfig(format!(
+                    "invalid fragment entry point {:?}",
+                    config.fragment_entry_point
+                ))
+            })?;
+
+            let shader_stages = [
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .module(shader_module.handle())
+                    .name(main_vs.as_c_str())
+                    .stage(vk::ShaderStageFlags::VERTEX)
+                    .build(),
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .module(shader_module.handle())
+                    .name(main_fs.as_c_str())
+                    .stage(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+            ];
+            let (binding_descriptions, attribute_descriptions) = match &config.geometry {
+                Some(geometry) => (
+                    vec![geometry.binding_description()],
+                    geometry.attribute_descriptions(),
+                ),
+                None => (Vec::new(), Vec::new()),
+            };
+            let vertex_input_state_create_info = vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(&binding_descriptions)
+                .vertex_attribute_descriptions(&attribute_descriptions);
+            let vertex_input_assembly_state_info =
+                vk::PipelineInputAssemblyStateCreateInfo::builder()
+                    .primitive_restart_enable(false)
+                    .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                    .build();
+
+            let viewport_state_create_info = vk::PipelineViewportStateCreateInfo::builder()
+                .scissors(&[vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                }])
+                .viewports(&[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }])
+                .build();
+
+            let rasterization_statue_create_info =
+                vk::PipelineRasterizationStateCreateInfo::builder()
+                    .depth_clamp_enable(false)
+                    // Loaded geometry does not necessarily wind its triangles consistently.
+                    .cull_mode(if config.geometry.is_some() {
+                        vk::CullModeFlags::NONE
+                    } else {
+                        vk::CullModeFlags::BACK
+                    })
+                    .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                    .line_width(1.0)
+                    .polygon_mode(vk::PolygonMode::FILL)
+                    .rasterizer_discard_enable(false)
+                    .depth_bias_enable(false)
+                    .build();
+
+            let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo::builder()
+                .rasterization_samples(samples)
+                .sample_shading_enable(config.multisample.min_sample_shading.is_some())
+                .min_sample_shading(config.multisample.min_sample_shading.unwrap_or(0.0))
+                .alpha_to_coverage_enable(false)
+                .alpha_to_coverage_enable(false)
+                .build();
+
+            let stencil_state = vk::StencilOpState {
+                fail_op: vk::StencilOp::KEEP,
+                pass_op: vk::StencilOp::KEEP,
+                depth_fail_op: vk::StencilOp::KEEP,
+                compare_op: vk::CompareOp::ALWAYS,
+                compare_mask: 0,
+                write_mask: 0,
+                reference: 0,
+            };
+
+            let depth_state_create_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(depth.is_some())
+                .depth_write_enable(depth.is_some())
+                .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+                .depth_bounds_test_enable(false)
+                .stencil_test_enable(false)
+                .front(stencil_state)
+                .back(stencil_state)
+                .max_depth_bounds(1.0)
+                .min_depth_bounds(0.0)
+                .build();
+
+            // Identical states for every attachment, so `independentBlend` is not needed.
+            let color_blend_attachment_states = vec![
+                vk::PipelineColorBlendAttachmentState {
+                    blend_enable: vk::FALSE,
+                    color_write_mask: vk::ColorComponentFlags::all(),
+                    src_color_blend_factor: vk::BlendFactor::ONE,
+                    dst_color_blend_factor: vk::BlendFactor::ZERO,
+                    color_blend_op: vk::BlendOp::ADD,
+                    src_alpha_blend_factor: vk::BlendFactor::ONE,
+                    dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+                    alpha_blend_op: vk::BlendOp::ADD,
+                };
+                color_attachments.len()
+            ];
+
+            let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+                .attachments(&color_blend_attachment_states)
+                .build();
+            let graphic_pipeline_create_infos = [vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input_state_create_info)
+                .input_assembly_state(&vertex_input_assembly_state_info)
+                .viewport_state(&viewport_state_create_info)
+                .rasterization_state(&rasterization_statue_create_info)
+                .multisample_state(&multisample_state_create_info)
+                .depth_stencil_state(&depth_state_create_info)
+                .color_blend_state(&color_blend_state)
+                .layout(pipeline_layout.handle())
+                .render_pass(render_pass.handle())
+                .subpass(0)
+                .base_pipeline_index(-1)
+                .build()];
+
+            let graphics_pipeline = unsafe {
+                device.create_graphics_pipelines(
+                    vk::PipelineCache::null(),
+                    &graphic_pipeline_create_infos,
+                    None,
+                )
+            }
+            .map_err(|(_, result)| result)?[0];
+
+            Pipeline::from_raw(&device, graphics_pipeline)
+        };
+        graphics_pipeline.set_name("triangle pipeline")?;
+
+        let framebuffer = {
+            let mut attachments: Vec<vk::ImageView> = color_attachments
+                .iter()
+                .map(ColorAttachment::render_target_view)
+                .collect();
+            if let Some(depth) = &depth {
+                attachments.push(depth.view.handle());
+            }
+            if samples != vk::SampleCountFlags::TYPE_1 {
+                attachments.extend(
+                    color_attachments
+                        .iter()
+                        .map(|attachment| attachment.view.handle()),
+                );
+            }
+
+            let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass.handle())
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1)
+                .build();
+
+            let framebuffer = unsafe { device.create_framebuffer(&framebuffer_create_info, None) }?;
+
+            Framebuffer::from_raw(&device, framebuffer)
+        };
+        framebuffer.set_name("triangle framebuffer")?;
+
+        let command_pool = {
+            let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(queue_family_index)
+                .build();
+
+            let command_pool =
+                unsafe { device.create_command_pool(&command_pool_create_info, None) }?;
+
+            CommandPool::from_raw(&device, command_pool)
+        };
+        command_pool.set_name("command pool")?;
+
+        // The ID shaders number triangles by vertex index, which only counts triangles without
+        // an index buffer.
+        let geometry = config
+            .geometry
+            .as_ref()
+            .map(|geometry| {
+                let deindexed;
+                let geometry = if config.fragment_entry_point == ID_ENTRY_POINT {
+                    deindexed = geometry.deindexed();
+                    &deindexed
+                } else {
+                    geometry
+                };
+                GeometryBuffers::upload(&device, graphics_queue, command_pool.handle(), geometry)
+            })
+            .transpose()?;
+
+        let command_buffer = {
+            let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(command_pool.handle())
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .build();
+
+            unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }?[0]
+        };
+        device.set_object_name(command_buffer, "render commands")?;
+
+        {
+            let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
+                .build();
+
+            unsafe { device.begin_command_buffer(command_buffer, &command_buffer_begin_info) }?;
+        }
+
+        {
+            let mut clear_values: Vec<vk::ClearValue> =
+                std::iter::once((config.color_format, config.clear_color))
+                    .chain(
+                        config
+                            .extra_color_attachments
+                            .iter()
+                            .map(|attachment| (attachment.format, attachment.clear_color)),
+                    )
+                    .map(|(format, [r, g, b, a])| vk::ClearValue {
+                        color: if format == ID_FORMAT {
+                            vk::ClearColorValue {
+                                uint32: [r as u32, g as u32, b as u32, a as u32],
+                            }
+                        } else {
+                            vk::ClearColorValue {
+                                float32: [r, g, b, a],
+                            }
+                        },
+                    })
+                    .collect();
+            if let Some(depth_config) = &config.depth {
+                clear_values.push(vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: depth_config.clear_depth,
+                        stencil: 0,
+                    },
+                });
+            }
+
+            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(render_pass.handle())
+                .framebuffer(framebuffer.handle())
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                })
+                .clear_values(&clear_values)
+                .build();
+
+            device.cmd_begin_label(command_buffer, "triangle pass", [1.0, 0.5, 0.0, 1.0]);
+
+            unsafe {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    graphics_pipeline.handle(),
+                );
+                match &geometry {
+                    Some(geometry) => {
+                        geometry.record_draw(&device, command_buffer, pipeline_layout.handle())
+                    }
+                    None => device.cmd_draw(command_buffer, 3, 1, 0, 0),
+                }
+
+                device.cmd_end_render_pass(command_buffer);
+            }
+
+            device.cmd_end_label(command_buffer);
+
+            unsafe { device.end_command_buffer(command_buffer) }?;
+        }
+
+        let fence = {
+            let fence_create_info = vk::FenceCreateInfo::builder()
+                .flags(vk::FenceCreateFlags::SIGNALED)
+                .build();
+
+            let fence = unsafe { device.create_fence(&fence_create_info, None) }?;
+
+            Fence::from_raw(&device, fence)
+        };
+        fence.set_name("render fence")?;
+
+        // transfer to host
+
+        let copy_cmd = {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(command_pool.handle())
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1)
+                .build();
+
+            unsafe { device.allocate_command_buffers(&allocate_info) }?[0]
+        };
+        device.set_object_name(copy_cmd, "readback commands")?;
+
+        {
+            let cmd_begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
+                .build();
+
+            unsafe { device.begin_command_buffer(copy_cmd, &cmd_begin_info) }?;
+        }
+
+        device.cmd_begin_label(copy_cmd, "readback", [0.0, 0.5, 1.0, 1.0]);
+
+        for attachment in &color_attachments {
+            attachment.readback.record_copy(
+                &device,
+                copy_cmd,
+                attachment.image.handle(),
+                vk::ImageAspectFlags::COLOR,
+                extent,
+            );
+        }
+        if let Some(DepthAttachment {
+            image: depth_image,
+            readback: Some(depth_readback),
+            ..
+        }) = &depth
+        {
+            depth_readback.record_copy(
+                &device,
+                copy_cmd,
+                depth_image.handle(),
+                vk::ImageAspectFlags::DEPTH,
+                extent,
+            );
+        }
+
+        device.cmd_end_label(copy_cmd);
+
+        unsafe { device.end_command_buffer(copy_cmd) }?;
+
+        Ok(Self {
+            graphics_queue,
+            command_buffer,
+            copy_cmd,
+            fence,
+            _command_pool: command_pool,
+            _framebuffer: framebuffer,
+            _graphics_pipeline: graphics_pipeline,
+            _pipeline_layout: pipeline_layout,
+            _render_pass: render_pass,
+            color_attachments,
+            depth,
+            _geometry: geometry,
+            device,
+            device_selection,
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &RenderConfig {
+        &self.config
+    }
+
+    /// Samples per pixel, after lowering [`MultisampleConfig::samples`] to what the device
+    /// supports.
+    ///
+    /// [`MultisampleConfig::samples`]: crate::MultisampleConfig::samples
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.color_attachments[0]
+            .multisample
+            .as_ref()
+            .map_or(vk::SampleCountFlags::TYPE_1, |multisample| {
+                multisample.samples
+            })
+    }
+
+    /// The depth attachment format negotiated from [`DepthConfig::formats`], if there is one.
+    pub fn depth_format(&self) -> Option<vk::Format> {
+        self.depth.as_ref().map(|depth| depth.format)
+    }
+
+    /// The physical device in use, and why every other device was passed over.
+    pub fn device_selection(&self) -> &DeviceSelection {
+        &self.device_selection
+    }
+
+    /// The logical device, for creating resources alongside the renderer's own.
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// Usage of the device memory blocks images and buffers are sub-allocated from.
+    pub fn memory_stats(&self) -> AllocatorStats {
+        self.device.memory_stats()
+    }
+
+    /// Names a Vulkan object, such as one created through [`OffscreenRenderer::device`], in
+    /// validation messages and tools like RenderDoc.
+    ///
+    /// The renderer names its own objects, e.g. `"color attachment"` and `"color readback image"`.
+    /// Does nothing when `VK_EXT_debug_utils` is not available.
+    pub fn set_object_name<H: vk::Handle>(&self, handle: H, name: &str) -> Result<()> {
+        Ok(self.device.set_object_name(handle, name)?)
+    }
+
+    /// Whether the validation layer was enabled; `false` when [`Validation::Optional`] fell back.
+    ///
+    /// [`Validation::Optional`]: crate::Validation::Optional
+    pub fn validation_enabled(&self) -> bool {
+        self.device.instance().validation_enabled()
+    }
+
+    /// Removes and returns the messages captured since the last call.
+    ///
+    /// Empty unless [`DebugMessengerConfig::capture`] is set and `VK_EXT_debug_utils` is
+    /// available; with only [`DebugMessengerConfig::fail_on_error`], errors not yet turned into
+    /// [`RenderError::Validation`].
+    ///
+    /// [`DebugMessengerConfig::capture`]: crate::DebugMessengerConfig::capture
+    /// [`DebugMessengerConfig::fail_on_error`]: crate::DebugMessengerConfig::fail_on_error
+    pub fn take_validation_messages(&self) -> Vec<ValidationMessage> {
+        self.device
+            .instance()
+            .validation_sink()
+            .map_or_else(Vec::new, ValidationSink::take)
+    }
+
+    /// Returns the captured errors as [`RenderError::Validation`] in fail-on-error mode.
+    fn check_validation_errors(&self) -> Result<()> {
+        if !self.config.debug_messenger.fail_on_error {
+            return Ok(());
+        }
+        match self.device.instance().validation_sink() {
+            Some(sink) => {
+                let errors = sink.take_errors();
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(RenderError::Validation(errors))
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Renders one frame and reads it back to host memory.
+    ///
+    /// In fail-on-error mode this fails with [`RenderError::Validation`] if errors were reported
+    /// since the previous render, including during setup, or while this frame was submitted.
+    pub fn render(&self) -> Result<RgbaImage> {
+        self.submit()?;
+
+        Ok(self.mapped_attachment(0)?.to_rgba_image())
+    }
+
+    /// Renders one frame like [`OffscreenRenderer::render`] but reads it back with 16 bits per
+    /// channel, for the 16-bit and float color formats.
+    pub fn render_rgba16(&self) -> Result<Rgba16Image> {
+        self.submit()?;
+
+        Ok(self.mapped_attachment(0)?.to_rgba16_image())
+    }
+
+    /// Renders one frame like [`OffscreenRenderer::render`] but reads it back as floats, keeping
+    /// values outside `0.0..=1.0` for the float color formats.
+    pub fn render_hdr(&self) -> Result<RgbaF32Image> {
+        self.submit()?;
+
+        Ok(self.mapped_attachment(0)?.to_rgba_f32_image())
+    }
+
+    /// Renders one frame like [`OffscreenRenderer::render`] but borrows the readback memory
+    /// instead of copying it. The view stays valid until the renderer is used again.
+    pub fn render_mapped(&mut self) -> Result<MappedImage<'_>> {
+        self.submit()?;
+
+        self.mapped_attachment(0)
+    }
+
+    /// Renders one frame like [`OffscreenRenderer::render_mapped`] and borrows the readback memory
+    /// of every color attachment, the first one followed by
+    /// [`RenderConfig::extra_color_attachments`].
+    pub fn render_attachments(&mut self) -> Result<Vec<MappedImage<'_>>> {
+        self.submit()?;
+
+        let renderer = &*self;
+        (0..renderer.color_attachments.len())
+            .map(|index| renderer.mapped_attachment(index))
+            .collect()
+    }
+
+    /// Copies the depth values of the last render, which needs [`DepthConfig::readback`].
+    pub fn depth_image(&self) -> Result<DepthImage> {
+        let (format, readback) = match &self.depth {
+            Some(DepthAttachment {
+                format,
+                readback: Some(readback),
+                ..
+            }) => (*format, readback),
+            _ => {
+                return Err(RenderError::InvalidConfig(
+                    "depth readback is not enabled".to_string(),
+                ))
+            }
+        };
+
+        let texel_size = depth::depth_copy_size(format);
+        let (data, row_pitch) = readback.mapped(&self.device, self.config.extent(), texel_size)?;
+
+        Ok(MappedImage {
+            width: self.config.width,
+            height: self.config.height,
+            format,
+            texel_size,
+            row_pitch,
+            data,
+        }
+        .into_depth_image())
+    }
+
+    /// Submits the render and readback command buffers and waits for them to finish.
+    fn submit(&self) -> Result<()> {
+        let device = &self.device;
+        let fence = self.fence.handle();
+
+        self.check_validation_errors()?;
+
+        {
+            let submit_infos = [vk::SubmitInfo::builder()
+                .command_buffers(&[self.command_buffer])
+                .build()];
+
+            unsafe {
+                device.reset_fences(&[fence])?;
+
+                device.queue_submit(self.graphics_queue, &submit_infos, fence)?;
+
+                device.wait_for_fences(&[fence], true, u64::MAX)?;
+            }
+        }
+
+        {
+            let submit_infos = [vk::SubmitInfo {
+                s_type: vk::StructureType::SUBMIT_INFO,
+                p_next: ptr::null(),
+                wait_semaphore_count: 0,
+                p_wait_semaphores: null(),
+                p_wait_dst_stage_mask: null(),
+                command_buffer_count: 1,
+                p_command_buffers: &self.copy_cmd,
+                signal_semaphore_count: 0,
+                p_signal_semaphores: null(),
+            }];
+
+            unsafe {
+                device.reset_fences(&[fence])?;
+
+                device.queue_submit(self.graphics_queue, &submit_infos, fence)?;
+
+                device.wait_for_fences(&[fence], true, u64::MAX)?;
+            }
+        }
+
+        self.check_validation_errors()
+    }
+
+    fn mapped_attachment(&self, index: usize) -> Result<MappedImage<'_>> {
+        let attachment = &self.color_attachments[index];
+        let texel_size = texel_size(attachment.format);
+        let (data, row_pitch) =
+            attachment
+                .readback
+                .mapped(&self.device, self.config.extent(), texel_size)?;
+
+        Ok(MappedImage {
+            width: self.config.width,
+            height: self.config.height,
+            format: attachment.format,
+            texel_size,
+            row_pitch,
+            data,
+        })
+    }
+}
+
+impl Drop for OffscreenRenderer {
+    fn drop(&mut self) {
+        // The remaining fields are destroyed by their own `Drop` impls once the GPU is idle.
+        unsafe {
+            self.device.device_wait_idle().ok();
+        }
+    }
+}
+
+/// A color attachment that is read back, its host copy and the multisampled image resolved into
+/// it, if any.
+///
+/// Fields are declared in destruction order.
+struct ColorAttachment {
+    format: vk::Format,
+    readback: Readback,
+    multisample: Option<MultisampleAttachment>,
+    view: ImageView,
+    image: Image,
+}
+
+impl ColorAttachment {
+    /// `name` prefixes the names of the Vulkan objects, e.g. `"color attachment"`.
+    fn new(
+        device: &Arc<Device>,
+        name: &str,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        readback_method: ReadbackMethod,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC);
+
+        let image = Image::new(device, &image_create_info, MemoryRequest::GPU_ONLY)?;
+        image.set_name(&format!("{} attachment", name))?;
+
+        let view = {
+            let image_view_create_info = vk::ImageViewCreateInfo::builder()
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image(image.handle());
+
+            let image_view = unsafe { device.create_image_view(&image_view_create_info, None) }?;
+
+            ImageView::from_raw(device, image_view)
+        };
+        view.set_name(&format!("{} attachment view", name))?;
+
+        let multisample = if samples != vk::SampleCountFlags::TYPE_1 {
+            Some(MultisampleAttachment::new(
+                device, name, format, samples, extent,
+            )?)
+        } else {
+            None
+        };
+
+        let readback = Readback::new(device, readback_method, format, extent, texel_size(format))?;
+        readback.set_name(&match readback_method {
+            ReadbackMethod::Buffer => format!("{} readback buffer", name),
+            ReadbackMethod::LinearImage => format!("{} readback image", name),
+        })?;
+
+        Ok(Self {
+            format,
+            readback,
+            multisample,
+            view,
+            image,
+        })
+    }
+
+    /// Rendered to directly, or written by the resolve, which overwrites every pixel so the
+    /// attachment need not be cleared.
+    fn description(&self) -> vk::AttachmentDescription {
+        vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format: self.format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: if self.multisample.is_some() {
+                vk::AttachmentLoadOp::DONT_CARE
+            } else {
+                vk::AttachmentLoadOp::CLEAR
+            },
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        }
+    }
+
+    /// The attachment the subpass renders into.
+    fn render_target_description(&self) -> vk::AttachmentDescription {
+        match &self.multisample {
+            Some(multisample) => multisample.description(self.format),
+            None => self.description(),
+        }
+    }
+
+    fn render_target_view(&self) -> vk::ImageView {
+        match &self.multisample {
+            Some(multisample) => multisample.view.handle(),
+            None => self.view.handle(),
+        }
+    }
+}
+
+/// The depth image, its view and, if it is read back, its host copy.
+///
+/// Fields are declared in destruction order.
+struct DepthAttachment {
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    readback: Option<Readback>,
+    view: ImageView,
+    image: Image,
+}
+
+impl DepthAttachment {
+    fn new(
+        device: &Arc<Device>,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        config: &DepthConfig,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let mut usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+        if config.readback {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage);
+
+        let image = Image::new(device, &image_create_info, MemoryRequest::GPU_ONLY)?;
+        image.set_name("depth attachment")?;
+
+        let view = {
+            let image_view_create_info = vk::ImageViewCreateInfo::builder()
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: depth::aspect_mask(format),
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image(image.handle());
+
+            let image_view = unsafe { device.create_image_view(&image_view_create_info, None) }?;
+
+            ImageView::from_raw(device, image_view)
+        };
+        view.set_name("depth attachment view")?;
+
+        // Depth formats rarely support linear tiling, so depth always goes through a buffer.
+        let readback = if config.readback {
+            let readback = Readback::new(
+                device,
+                ReadbackMethod::Buffer,
+                format,
+                extent,
+                depth::depth_copy_size(format),
+            )?;
+            readback.set_name("depth readback buffer")?;
+            Some(readback)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            format,
+            samples,
+            readback,
+            view,
+            image,
+        })
+    }
+
+    fn description(&self) -> vk::AttachmentDescription {
+        let (store_op, final_layout) = if self.readback.is_some() {
+            (
+                vk::AttachmentStoreOp::STORE,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )
+        } else {
+            (
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            )
+        };
+
+        vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format: self.format,
+            samples: self.samples,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op,
+            stencil_load_op: if depth::has_stencil(self.format) {
+                vk::AttachmentLoadOp::CLEAR
+            } else {
+                vk::AttachmentLoadOp::DONT_CARE
+            },
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout,
+        }
+    }
+}
+
+/// The multisampled color image the pass renders into before resolving it, and its view.
+///
+/// Fields are declared in destruction order.
+struct MultisampleAttachment {
+    samples: vk::SampleCountFlags,
+    view: ImageView,
+    _image: Image,
+}
+
+impl MultisampleAttachment {
+    fn new(
+        device: &Arc<Device>,
+        name: &str,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        // Only the resolved image is ever read, so the samples never need to leave the GPU tiles.
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            );
+
+        let image = Image::new(device, &image_create_info, MemoryRequest::GPU_ONLY)?;
+        image.set_name(&format!("multisampled {} attachment", name))?;
+
+        let view = {
+            let image_view_create_info = vk::ImageViewCreateInfo::builder()
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image(image.handle());
+
+            let image_view = unsafe { device.create_image_view(&image_view_create_info, None) }?;
+
+            ImageView::from_raw(device, image_view)
+        };
+        view.set_name(&format!("multisampled {} attachment view", name))?;
+
+        Ok(Self {
+            samples,
+            view,
+            _image: image,
+        })
+    }
+
+    /// Cleared and rendered to, then discarded once resolved.
+    fn description(&self, format: vk::Format) -> vk::AttachmentDescription {
+        vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format,
+            samples: self.samples,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }
+    }
+}
+
+/// Checks that `physical_device` can render to and read back the color attachments described by
+/// `config`.
+fn check_render_target_support(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    config: &RenderConfig,
+) -> Result<()> {
+    let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+
+    if config.width > limits.max_image_dimension2_d || config.height > limits.max_image_dimension2_d
+    {
+        return Err(RenderError::InvalidConfig(format!(
+            "extent {}x{} exceeds the device limit of {}",
+            config.width, config.height, limits.max_image_dimension2_d
+        )));
+    }
+
+    let color_formats = config.color_formats();
+    if color_formats.len() > limits.max_color_attachments as usize {
+        return Err(RenderError::InvalidConfig(format!(
+            "{} color attachments exceed the device limit of {}",
+            color_formats.len(),
+            limits.max_color_attachments
+        )));
+    }
+
+    for format in color_formats {
+        if !supports_color_target(instance, physical_device, format, config.readback) {
+            return Err(RenderError::UnsupportedFormat(format));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `physical_device` can render to `format` and copy it to host memory with `readback`.
+fn supports_color_target(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+    readback: ReadbackMethod,
+) -> bool {
+    let format_properties =
+        unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+
+    format_properties
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::TRANSFER_SRC)
+        && (readback != ReadbackMethod::LinearImage
+            || format_properties
+                .linear_tiling_features
+                .contains(vk::FormatFeatureFlags::TRANSFER_DST))
+}
+
+unsafe fn create_shader_module(device: &ash::Device, code: &[u32]) -> VkResult<vk::ShaderModule> {
+    let shader_module_create_info = vk::ShaderModuleCreateInfo {
+        s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::ShaderModuleCreateFlags::empty(),
+        code_size: code.len() * 4,
+        p_code: code.as_ptr(),
+    };
+
+    device.create_shader_module(&shader_module_create_info, None)
+}
+
+/// Bytes per texel of the color formats in [`SUPPORTED_COLOR_FORMATS`] and [`ID_FORMAT`].
+fn texel_size(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | ID_FORMAT => 4,
+        vk::Format::R16G16B16A16_UNORM | vk::Format::R16G16B16A16_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => unreachable!("unsupported color format {:?}", format),
+    }
+}
@@ -0,0 +1,325 @@
+//! Diagnostic report of everything the renderer can see on this machine.
+
+use std::fmt;
+
+use ash::vk;
+use serde_json::{json, Value};
+
+use crate::{
+    debug::DebugMessengerConfig,
+    device::{
+        c_chars_to_string, enumerate_physical_devices, format_uuid, format_version,
+        select_physical_device, DeviceSelector, PhysicalDeviceInfo, RejectionReason,
+    },
+    error::Result,
+    features::{DeviceRequirements, EnabledCapabilities, Feature},
+    instance::{create_instance, Validation},
+};
+
+/// Color formats probed for `COLOR_ATTACHMENT` support.
+const PROBED_COLOR_FORMATS: &[vk::Format] = &[
+    vk::Format::R8_UNORM,
+    vk::Format::R8G8_UNORM,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_UNORM,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::B10G11R11_UFLOAT_PACK32,
+    vk::Format::R16G16B16A16_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32_UINT,
+    vk::Format::R32_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT,
+];
+
+pub struct SystemReport {
+    pub instance_api_version: u32,
+    pub instance_layers: Vec<(String, u32)>,
+    pub instance_extensions: Vec<(String, u32)>,
+    pub devices: Vec<DeviceReport>,
+}
+
+pub struct DeviceReport {
+    pub info: PhysicalDeviceInfo,
+    /// `None` for the device the renderer would pick.
+    pub rejection: Option<RejectionReason>,
+    /// What the renderer would enable on the device it picks; `None` for every other device.
+    pub enabled: Option<EnabledCapabilities>,
+    pub queue_families: Vec<vk::QueueFamilyProperties>,
+    pub memory_types: Vec<vk::MemoryType>,
+    pub color_attachment_formats: Vec<vk::Format>,
+}
+
+/// Enumerates layers, extensions and every physical device, and records which device would be
+/// selected for `selector` and `requirements`.
+pub fn system_report(
+    validation: Validation,
+    debug_messenger: &DebugMessengerConfig,
+    selector: Option<&DeviceSelector>,
+    requirements: &DeviceRequirements,
+) -> Result<SystemReport> {
+    let instance = create_instance(validation, debug_messenger)?;
+    let entry = instance.entry();
+
+    let instance_api_version = entry
+        .try_enumerate_instance_version()?
+        .unwrap_or(vk::API_VERSION_1_0);
+    let instance_layers = entry
+        .enumerate_instance_layer_properties()?
+        .iter()
+        .map(|layer| (c_chars_to_string(&layer.layer_name), layer.spec_version))
+        .collect();
+    let instance_extensions = entry
+        .enumerate_instance_extension_properties()?
+        .iter()
+        .map(|extension| {
+            (
+                c_chars_to_string(&extension.extension_name),
+                extension.spec_version,
+            )
+        })
+        .collect();
+
+    let infos = enumerate_physical_devices(&instance)?;
+    let (selected, rejected) = match select_physical_device(infos.clone(), selector, requirements) {
+        Ok(selection) => (
+            Some((selection.device.index, selection.capabilities)),
+            selection.rejected,
+        ),
+        Err(rejected) => (None, rejected),
+    };
+
+    let devices = infos
+        .into_iter()
+        .map(|info| {
+            let rejection = rejected
+                .iter()
+                .find(|device| device.index == info.index)
+                .map(|device| device.reason.clone());
+            let enabled = match &selected {
+                Some((index, capabilities)) if *index == info.index => Some(capabilities.clone()),
+                _ => None,
+            };
+
+            DeviceReport::query(&instance, info, rejection, enabled)
+        })
+        .collect();
+
+    Ok(SystemReport {
+        instance_api_version,
+        instance_layers,
+        instance_extensions,
+        devices,
+    })
+}
+
+impl DeviceReport {
+    fn query(
+        instance: &ash::Instance,
+        info: PhysicalDeviceInfo,
+        rejection: Option<RejectionReason>,
+        enabled: Option<EnabledCapabilities>,
+    ) -> Self {
+        let physical_device = info.handle;
+
+        let queue_families =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let memory_types =
+            memory_properties.memory_types[..memory_properties.memory_type_count as usize].to_vec();
+
+        let color_attachment_formats = PROBED_COLOR_FORMATS
+            .iter()
+            .copied()
+            .filter(|&format| {
+                unsafe { instance.get_physical_device_format_properties(physical_device, format) }
+                    .optimal_tiling_features
+                    .contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT)
+            })
+            .collect();
+
+        Self {
+            info,
+            rejection,
+            enabled,
+            queue_families,
+            memory_types,
+            color_attachment_formats,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let info = &self.info;
+
+        json!({
+            "index": info.index,
+            "name": info.name,
+            "type": format!("{:?}", info.device_type),
+            "vendor_id": info.vendor_id,
+            "device_id": info.device_id,
+            "api_version": format_version(info.api_version),
+            "driver_version": info.driver_version,
+            "driver_name": info.driver_name,
+            "driver_info": info.driver_info,
+            "uuid": format_uuid(&info.uuid),
+            "selected": self.rejection.is_none(),
+            "rejection": self.rejection.as_ref().map(|reason| reason.to_string()),
+            "queue_families": self.queue_families.iter().enumerate().map(|(i, family)| json!({
+                "index": i,
+                "flags": format!("{:?}", family.queue_flags),
+                "count": family.queue_count,
+                "timestamp_valid_bits": family.timestamp_valid_bits,
+            })).collect::<Vec<_>>(),
+            "memory_heaps": info.memory_heaps.iter().enumerate().map(|(i, heap)| json!({
+                "index": i,
+                "size": heap.size,
+                "flags": format!("{:?}", heap.flags),
+            })).collect::<Vec<_>>(),
+            "memory_types": self.memory_types.iter().enumerate().map(|(i, memory_type)| json!({
+                "index": i,
+                "heap_index": memory_type.heap_index,
+                "flags": format!("{:?}", memory_type.property_flags),
+            })).collect::<Vec<_>>(),
+            "color_attachment_formats": self.color_attachment_formats
+                .iter()
+                .map(|format| format!("{:?}", format))
+                .collect::<Vec<_>>(),
+            "extensions": {
+                "supported": info.extensions.iter().map(|(name, version)| json!({
+                    "name": name,
+                    "spec_version": version,
+                })).collect::<Vec<_>>(),
+                "enabled": self.enabled.as_ref().map(|enabled| &enabled.extensions),
+            },
+            "features": {
+                "supported": info.features.iter().map(Feature::name).collect::<Vec<_>>(),
+                "enabled": self.enabled.as_ref().map(|enabled| {
+                    enabled.features.iter().map(Feature::name).collect::<Vec<_>>()
+                }),
+            },
+        })
+    }
+}
+
+impl SystemReport {
+    pub fn to_json(&self) -> Value {
+        let named = |list: &[(String, u32)]| -> Vec<Value> {
+            list.iter()
+                .map(|(name, version)| json!({ "name": name, "spec_version": version }))
+                .collect()
+        };
+
+        json!({
+            "instance": {
+                "api_version": format_version(self.instance_api_version),
+                "layers": named(&self.instance_layers),
+                "extensions": named(&self.instance_extensions),
+            },
+            "devices": self.devices.iter().map(DeviceReport::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl fmt::Display for SystemReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Vulkan instance {}",
+            format_version(self.instance_api_version)
+        )?;
+        writeln!(f, "  layers:")?;
+        for (name, version) in &self.instance_layers {
+            writeln!(f, "    {} (spec {})", name, format_version(*version))?;
+        }
+        writeln!(f, "  extensions:")?;
+        for (name, version) in &self.instance_extensions {
+            writeln!(f, "    {} v{}", name, version)?;
+        }
+
+        for device in &self.devices {
+            writeln!(f)?;
+            write!(f, "{}", device)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DeviceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let info = &self.info;
+
+        writeln!(f, "[{}] {} ({:?})", info.index, info.name, info.device_type)?;
+        match &self.rejection {
+            None => writeln!(f, "  status: selected")?,
+            Some(reason) => writeln!(f, "  status: not used, {}", reason)?,
+        }
+        writeln!(
+            f,
+            "  vendor: {:#06x}, device: {:#06x}, uuid: {}",
+            info.vendor_id,
+            info.device_id,
+            format_uuid(&info.uuid)
+        )?;
+        writeln!(
+            f,
+            "  api: {}, driver: {} {} ({:#x})",
+            format_version(info.api_version),
+            info.driver_name,
+            info.driver_info,
+            info.driver_version
+        )?;
+
+        writeln!(f, "  queue families:")?;
+        for (i, family) in self.queue_families.iter().enumerate() {
+            writeln!(
+                f,
+                "    {}: {} x {:?}",
+                i, family.queue_count, family.queue_flags
+            )?;
+        }
+
+        writeln!(f, "  memory heaps:")?;
+        for (i, heap) in info.memory_heaps.iter().enumerate() {
+            writeln!(
+                f,
+                "    {}: {} MiB {:?}",
+                i,
+                heap.size / (1024 * 1024),
+                heap.flags
+            )?;
+        }
+
+        writeln!(f, "  memory types:")?;
+        for (i, memory_type) in self.memory_types.iter().enumerate() {
+            writeln!(
+                f,
+                "    {}: heap {} {:?}",
+                i, memory_type.heap_index, memory_type.property_flags
+            )?;
+        }
+
+        let formats: Vec<String> = self
+            .color_attachment_formats
+            .iter()
+            .map(|format| format!("{:?}", format))
+            .collect();
+        writeln!(f, "  color attachment formats: {}", formats.join(", "))?;
+        let features: Vec<&str> = info.features.iter().map(Feature::name).collect();
+        writeln!(f, "  features: {}", features.join(", "))?;
+
+        writeln!(f, "  extensions:")?;
+        for (name, version) in &info.extensions {
+            writeln!(f, "    {} v{}", name, version)?;
+        }
+
+        if let Some(enabled) = &self.enabled {
+            let features: Vec<&str> = enabled.features.iter().map(Feature::name).collect();
+            writeln!(f, "  enabled features: {}", features.join(", "))?;
+            writeln!(f, "  enabled extensions: {}", enabled.extensions.join(", "))?;
+        }
+        Ok(())
+    }
+}
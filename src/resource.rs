@@ -0,0 +1,479 @@
+//! Owning wrappers around Vulkan handles.
+//!
+//! Every wrapper that is created from a device keeps an [`Arc`] to that [`Device`], and the
+//! device keeps an [`Arc`] to its [`Instance`]. Dropping wrappers in any order therefore
+//! destroys child objects first, then the device and finally the instance.
+
+use std::{
+    ffi::CString,
+    ops::Deref,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use ash::{extensions::ext::DebugUtils, prelude::VkResult, vk};
+
+use crate::{
+    allocator::{Allocation, AllocatorStats, BlockAllocator, ResourceKind},
+    debug::ValidationSink,
+    error::Result,
+    features::EnabledCapabilities,
+    memory::{
+        heap_budgets, select_memory_type, HeapBudget, MemoryRequest, MEMORY_BUDGET_EXTENSION,
+    },
+};
+
+/// `ash::Entry` together with the `VkInstance` created from it and, when `VK_EXT_debug_utils`
+/// is enabled, a debug messenger that lives as long as the instance.
+pub struct Instance {
+    entry: ash::Entry,
+    handle: ash::Instance,
+    validation: bool,
+    debug_utils: Option<DebugUtils>,
+    debug_messenger: vk::DebugUtilsMessengerEXT,
+    validation_sink: Option<Arc<ValidationSink>>,
+}
+
+impl Instance {
+    /// Takes ownership of `handle`, which must have been created from `entry`, with the
+    /// validation layer enabled if `validation` is set.
+    pub fn new(entry: ash::Entry, handle: ash::Instance, validation: bool) -> Self {
+        Self {
+            entry,
+            handle,
+            validation,
+            debug_utils: None,
+            debug_messenger: vk::DebugUtilsMessengerEXT::null(),
+            validation_sink: None,
+        }
+    }
+
+    /// Creates the debug messenger. The instance must have `VK_EXT_debug_utils` enabled.
+    ///
+    /// `sink` must be the one `create_info` points its user data at; it is kept alive until the
+    /// instance is destroyed. On failure the instance is destroyed along with `self`.
+    pub fn with_debug_messenger(
+        mut self,
+        create_info: &vk::DebugUtilsMessengerCreateInfoEXT,
+        sink: Option<Arc<ValidationSink>>,
+    ) -> VkResult<Self> {
+        self.validation_sink = sink;
+
+        let debug_utils = DebugUtils::new(&self.entry, &self.handle);
+        self.debug_messenger =
+            unsafe { debug_utils.create_debug_utils_messenger(create_info, None) }?;
+        self.debug_utils = Some(debug_utils);
+
+        Ok(self)
+    }
+
+    pub fn entry(&self) -> &ash::Entry {
+        &self.entry
+    }
+
+    /// Whether `VK_LAYER_KHRONOS_validation` is enabled.
+    pub fn validation_enabled(&self) -> bool {
+        self.validation
+    }
+
+    /// Whether `VK_EXT_debug_utils` is enabled.
+    pub fn debug_utils_enabled(&self) -> bool {
+        self.debug_utils.is_some()
+    }
+
+    /// `VK_EXT_debug_utils` entry points, if the extension is enabled.
+    pub fn debug_utils(&self) -> Option<&DebugUtils> {
+        self.debug_utils.as_ref()
+    }
+
+    /// Messages captured by the debug messenger, if capture is enabled.
+    pub fn validation_sink(&self) -> Option<&ValidationSink> {
+        self.validation_sink.as_deref()
+    }
+}
+
+impl Deref for Instance {
+    type Target = ash::Instance;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(debug_utils) = &self.debug_utils {
+                debug_utils.destroy_debug_utils_messenger(self.debug_messenger, None);
+            }
+            self.handle.destroy_instance(None);
+        }
+    }
+}
+
+/// Logical device plus the physical device it was created from.
+pub struct Device {
+    instance: Arc<Instance>,
+    physical_device: vk::PhysicalDevice,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    capabilities: EnabledCapabilities,
+    allocator: Mutex<BlockAllocator>,
+    handle: ash::Device,
+}
+
+impl Device {
+    /// Takes ownership of `handle`, which must have been created from `physical_device` with
+    /// `capabilities` enabled.
+    pub fn new(
+        instance: Arc<Instance>,
+        physical_device: vk::PhysicalDevice,
+        capabilities: EnabledCapabilities,
+        handle: ash::Device,
+    ) -> Self {
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+
+        Self {
+            instance,
+            physical_device,
+            memory_properties,
+            capabilities,
+            allocator: Mutex::new(BlockAllocator::new(limits.buffer_image_granularity)),
+            handle,
+        }
+    }
+
+    pub fn instance(&self) -> &Arc<Instance> {
+        &self.instance
+    }
+
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.physical_device
+    }
+
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory_properties
+    }
+
+    /// Features and extensions enabled on the device.
+    pub fn capabilities(&self) -> &EnabledCapabilities {
+        &self.capabilities
+    }
+
+    /// Current heap budgets, exact when `VK_EXT_memory_budget` is enabled.
+    pub fn heap_budgets(&self) -> Vec<HeapBudget> {
+        let memory_budget = self
+            .capabilities
+            .extensions
+            .iter()
+            .any(|name| name == MEMORY_BUDGET_EXTENSION);
+
+        heap_budgets(&self.instance, self.physical_device, memory_budget)
+    }
+
+    /// See [`select_memory_type`]; budgets are queried fresh for every call.
+    pub fn select_memory_type(
+        &self,
+        requirements: &vk::MemoryRequirements,
+        request: MemoryRequest,
+    ) -> Result<u32> {
+        select_memory_type(
+            &self.memory_properties,
+            requirements,
+            request,
+            &self.heap_budgets(),
+        )
+    }
+
+    /// Sub-allocates memory of the type chosen by [`Device::select_memory_type`] from a shared
+    /// block. Host-visible memory comes mapped; see [`Allocation::mapped_ptr`].
+    pub fn allocate(
+        &self,
+        requirements: &vk::MemoryRequirements,
+        request: MemoryRequest,
+        kind: ResourceKind,
+    ) -> Result<Allocation> {
+        let memory_type_index = self.select_memory_type(requirements, request)?;
+        let (allocation, new_block) = self.lock_allocator().allocate(
+            &self.handle,
+            &self.memory_properties,
+            requirements,
+            memory_type_index,
+            kind,
+        )?;
+
+        if let Some(memory) = new_block {
+            let name = format!("memory block (type {})", memory_type_index);
+            self.set_object_name(memory, &name)?;
+        }
+
+        Ok(allocation)
+    }
+
+    /// Returns `allocation` to its block. Whatever is bound to it must be destroyed first.
+    pub fn free(&self, allocation: Allocation) {
+        self.lock_allocator().free(&self.handle, allocation);
+    }
+
+    /// Block and allocation counts and sizes of the memory allocator.
+    pub fn memory_stats(&self) -> AllocatorStats {
+        self.lock_allocator().stats()
+    }
+
+    fn lock_allocator(&self) -> MutexGuard<'_, BlockAllocator> {
+        // Every allocator operation leaves it consistent before it can panic.
+        self.allocator.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Names `handle` in validation messages and tools such as RenderDoc.
+    ///
+    /// Does nothing when `VK_EXT_debug_utils` is not enabled. `name` is cut at the first NUL.
+    pub fn set_object_name<H: vk::Handle>(&self, handle: H, name: &str) -> VkResult<()> {
+        let debug_utils = match self.instance.debug_utils() {
+            Some(debug_utils) => debug_utils,
+            None => return Ok(()),
+        };
+
+        let name = debug_name(name);
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(H::TYPE)
+            .object_handle(handle.as_raw())
+            .object_name(&name);
+
+        unsafe { debug_utils.debug_utils_set_object_name(self.handle.handle(), &name_info) }
+    }
+
+    /// Opens a labelled region in `command_buffer`, closed by [`Device::cmd_end_label`].
+    ///
+    /// Does nothing when `VK_EXT_debug_utils` is not enabled.
+    pub fn cmd_begin_label(&self, command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils) = self.instance.debug_utils() {
+            let name = debug_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+
+            unsafe { debug_utils.cmd_begin_debug_utils_label(command_buffer, &label) };
+        }
+    }
+
+    pub fn cmd_end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils) = self.instance.debug_utils() {
+            unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+}
+
+fn debug_name(name: &str) -> CString {
+    let name = name.split('\0').next().unwrap_or_default();
+    CString::new(name).expect("interior NUL bytes are cut off")
+}
+
+impl Deref for Device {
+    type Target = ash::Device;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe {
+            self.handle.device_wait_idle().ok();
+            self.lock_allocator().destroy(&self.handle);
+            self.handle.destroy_device(None);
+        }
+    }
+}
+
+macro_rules! device_handle {
+    ($(#[$meta:meta])* $name:ident, $handle:ty, $destroy:ident) => {
+        $(#[$meta])*
+        pub struct $name {
+            device: Arc<Device>,
+            handle: $handle,
+        }
+
+        impl $name {
+            /// Takes ownership of `handle`, which must have been created from `device`.
+            pub fn from_raw(device: &Arc<Device>, handle: $handle) -> Self {
+                Self {
+                    device: device.clone(),
+                    handle,
+                }
+            }
+
+            pub fn handle(&self) -> $handle {
+                self.handle
+            }
+
+            pub fn device(&self) -> &Arc<Device> {
+                &self.device
+            }
+
+            /// See [`Device::set_object_name`].
+            pub fn set_name(&self, name: &str) -> VkResult<()> {
+                self.device.set_object_name(self.handle, name)
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                unsafe {
+                    self.device.$destroy(self.handle, None);
+                }
+            }
+        }
+    };
+}
+
+device_handle!(ImageView, vk::ImageView, destroy_image_view);
+device_handle!(Framebuffer, vk::Framebuffer, destroy_framebuffer);
+device_handle!(RenderPass, vk::RenderPass, destroy_render_pass);
+device_handle!(PipelineLayout, vk::PipelineLayout, destroy_pipeline_layout);
+device_handle!(Pipeline, vk::Pipeline, destroy_pipeline);
+device_handle!(ShaderModule, vk::ShaderModule, destroy_shader_module);
+device_handle!(
+    /// Command pool; command buffers allocated from it are freed together with the pool.
+    CommandPool,
+    vk::CommandPool,
+    destroy_command_pool
+);
+device_handle!(Fence, vk::Fence, destroy_fence);
+
+/// A `VkImage` bound to sub-allocated memory.
+pub struct Image {
+    device: Arc<Device>,
+    handle: vk::Image,
+    /// `None` only while the image is being created.
+    allocation: Option<Allocation>,
+}
+
+impl Image {
+    /// Creates an image and binds it to memory sub-allocated with [`Device::allocate`].
+    pub fn new(
+        device: &Arc<Device>,
+        create_info: &vk::ImageCreateInfo,
+        memory_request: MemoryRequest,
+    ) -> Result<Self> {
+        let handle = unsafe { device.create_image(create_info, None) }?;
+
+        // Own the image before allocating so that it is destroyed if allocation fails.
+        let mut image = Self {
+            device: device.clone(),
+            handle,
+            allocation: None,
+        };
+
+        let kind = if create_info.tiling == vk::ImageTiling::OPTIMAL {
+            ResourceKind::Optimal
+        } else {
+            ResourceKind::Linear
+        };
+        let mem_reqs = unsafe { device.get_image_memory_requirements(handle) };
+        let allocation = device.allocate(&mem_reqs, memory_request, kind)?;
+        let (memory, offset) = (allocation.memory(), allocation.offset());
+        image.allocation = Some(allocation);
+
+        unsafe { device.bind_image_memory(handle, memory, offset) }?;
+
+        Ok(image)
+    }
+
+    pub fn handle(&self) -> vk::Image {
+        self.handle
+    }
+
+    /// The memory range the image is bound to.
+    pub fn allocation(&self) -> &Allocation {
+        self.allocation
+            .as_ref()
+            .expect("images are bound to memory once created")
+    }
+
+    /// See [`Device::set_object_name`].
+    pub fn set_name(&self, name: &str) -> VkResult<()> {
+        self.device.set_object_name(self.handle, name)
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image(self.handle, None);
+        }
+        if let Some(allocation) = self.allocation.take() {
+            self.device.free(allocation);
+        }
+    }
+}
+
+/// A `VkBuffer` bound to sub-allocated memory.
+pub struct Buffer {
+    device: Arc<Device>,
+    handle: vk::Buffer,
+    size: vk::DeviceSize,
+    /// `None` only while the buffer is being created.
+    allocation: Option<Allocation>,
+}
+
+impl Buffer {
+    /// Creates a buffer and binds it to memory sub-allocated with [`Device::allocate`].
+    pub fn new(
+        device: &Arc<Device>,
+        create_info: &vk::BufferCreateInfo,
+        memory_request: MemoryRequest,
+    ) -> Result<Self> {
+        let handle = unsafe { device.create_buffer(create_info, None) }?;
+
+        // Own the buffer before allocating so that it is destroyed if allocation fails.
+        let mut buffer = Self {
+            device: device.clone(),
+            handle,
+            size: create_info.size,
+            allocation: None,
+        };
+
+        let mem_reqs = unsafe { device.get_buffer_memory_requirements(handle) };
+        let allocation = device.allocate(&mem_reqs, memory_request, ResourceKind::Linear)?;
+        let (memory, offset) = (allocation.memory(), allocation.offset());
+        buffer.allocation = Some(allocation);
+
+        unsafe { device.bind_buffer_memory(handle, memory, offset) }?;
+
+        Ok(buffer)
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.handle
+    }
+
+    /// Size the buffer was created with.
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// The memory range the buffer is bound to.
+    pub fn allocation(&self) -> &Allocation {
+        self.allocation
+            .as_ref()
+            .expect("buffers are bound to memory once created")
+    }
+
+    /// See [`Device::set_object_name`].
+    pub fn set_name(&self, name: &str) -> VkResult<()> {
+        self.device.set_object_name(self.handle, name)
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.handle, None);
+        }
+        if let Some(allocation) = self.allocation.take() {
+            self.device.free(allocation);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Binary and ASCII STL parsing.
+
+use std::convert::TryInto;
+
+use crate::{
+    geometry::MeshVertex,
+    mesh::{cross, normalize, sub, Mesh, DEFAULT_VERTEX_COLOR},
+};
+
+/// Header, then the triangle count.
+const BINARY_HEADER_SIZE: usize = 84;
+/// Normal, three corners and an attribute byte count.
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+/// Parses an STL file into a mesh with flat shaded triangles: each triangle gets its own three
+/// vertices with the facet normal, computed from the corners where the file leaves it zero.
+pub fn parse(data: &[u8]) -> Result<Mesh, String> {
+    let triangles = if is_binary(data) {
+        parse_binary(data)?
+    } else {
+        parse_ascii(data)?
+    };
+
+    let mut mesh = Mesh::default();
+    for (normal, corners) in triangles {
+        let normal = if normal.iter().all(|&n| n == 0.0) {
+            cross(sub(corners[1], corners[0]), sub(corners[2], corners[0]))
+        } else {
+            normal
+        };
+        let normal = normalize(normal);
+
+        mesh.indices
+            .extend(mesh.vertices.len() as u32..mesh.vertices.len() as u32 + 3);
+        mesh.vertices
+            .extend(corners.iter().map(|&position| MeshVertex {
+                position,
+                normal,
+                uv: [0.0; 2],
+                color: DEFAULT_VERTEX_COLOR,
+            }));
+    }
+
+    Ok(mesh)
+}
+
+type Triangle = ([f32; 3], [[f32; 3]; 3]);
+
+/// Binary files may also start with `solid`, so tell them apart by whether the size matches the
+/// triangle count in the header.
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < BINARY_HEADER_SIZE {
+        return false;
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+
+    !data.starts_with(b"solid")
+        || Some(data.len())
+            == count
+                .checked_mul(BINARY_TRIANGLE_SIZE)
+                .and_then(|size| size.checked_add(BINARY_HEADER_SIZE))
+}
+
+fn parse_binary(data: &[u8]) -> Result<Vec<Triangle>, String> {
+    if data.len() < BINARY_HEADER_SIZE {
+        return Err("binary STL header is truncated".to_string());
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    let body = &data[BINARY_HEADER_SIZE..];
+    if body.len() / BINARY_TRIANGLE_SIZE < count {
+        return Err(format!(
+            "binary STL declares {} triangles but holds {}",
+            count,
+            body.len() / BINARY_TRIANGLE_SIZE
+        ));
+    }
+
+    Ok(body
+        .chunks_exact(BINARY_TRIANGLE_SIZE)
+        .take(count)
+        .map(|triangle| {
+            let vec3 = |i: usize| {
+                let float = |j: usize| {
+                    let offset = (i * 3 + j) * 4;
+                    f32::from_le_bytes(triangle[offset..offset + 4].try_into().unwrap())
+                };
+                [float(0), float(1), float(2)]
+            };
+            (vec3(0), [vec3(1), vec3(2), vec3(3)])
+        })
+        .collect())
+}
+
+fn parse_ascii(data: &[u8]) -> Result<Vec<Triangle>, String> {
+    let text = std::str::from_utf8(data).map_err(|_| "ASCII STL is not valid UTF-8".to_string())?;
+    let mut tokens = text.split_whitespace();
+
+    if tokens.next() != Some("solid") {
+        return Err("ASCII STL does not start with 'solid'".to_string());
+    }
+
+    let mut triangles = Vec::new();
+    let mut normal = [0.0; 3];
+    let mut corners = Vec::with_capacity(3);
+    while let Some(token) = tokens.next() {
+        match token {
+            "normal" => normal = parse_vec3(&mut tokens)?,
+            "vertex" => corners.push(parse_vec3(&mut tokens)?),
+            "endfacet" => {
+                if corners.len() != 3 {
+                    return Err(format!(
+                        "facet {} has {} vertices, expected 3",
+                        triangles.len() + 1,
+                        corners.len()
+                    ));
+                }
+                triangles.push((normal, [corners[0], corners[1], corners[2]]));
+                normal = [0.0; 3];
+                corners.clear();
+            }
+            // `facet`, `outer loop`, `endloop`, and the solid names after `solid` and `endsolid`.
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+fn parse_vec3<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<[f32; 3], String> {
+    let mut vec3 = [0.0; 3];
+    for value in vec3.iter_mut() {
+        let token = tokens
+            .next()
+            .ok_or_else(|| "ASCII STL ends inside a vector".to_string())?;
+        *value = token
+            .parse()
+            .map_err(|_| format!("invalid number '{}' in ASCII STL", token))?;
+    }
+    Ok(vec3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: &str = "solid triangle
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid triangle
+";
+
+    fn binary(header: &[u8], count: u32, triangles: &[Triangle]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, 0);
+        data.extend_from_slice(&count.to_le_bytes());
+        for (normal, corners) in triangles {
+            for vec3 in std::iter::once(normal).chain(corners) {
+                for value in vec3 {
+                    data.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            data.extend_from_slice(&[0, 0]);
+        }
+        data
+    }
+
+    fn triangle(normal: [f32; 3]) -> Triangle {
+        (normal, [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+    }
+
+    fn expected_triangle() -> Mesh {
+        let (_, corners) = triangle([0.0; 3]);
+        Mesh {
+            vertices: corners
+                .iter()
+                .map(|&position| MeshVertex {
+                    position,
+                    normal: [0.0, 0.0, 1.0],
+                    uv: [0.0; 2],
+                    color: DEFAULT_VERTEX_COLOR,
+                })
+                .collect(),
+            indices: vec![0, 1, 2],
+        }
+    }
+
+    #[test]
+    fn ascii() {
+        // Longer than a binary header, but its size does not match one.
+        assert!(ASCII.len() > BINARY_HEADER_SIZE);
+        assert!(!is_binary(ASCII.as_bytes()));
+        assert_eq!(parse(ASCII.as_bytes()), Ok(expected_triangle()));
+    }
+
+    #[test]
+    fn binary_detection() {
+        let plain = binary(b"exported", 1, &[triangle([0.0; 3])]);
+        assert!(is_binary(&plain));
+        assert_eq!(parse(&plain), Ok(expected_triangle()));
+
+        // Many exporters start binary headers with `solid` too.
+        let solid = binary(b"solid exported", 1, &[triangle([0.0, 0.0, 2.0])]);
+        assert!(is_binary(&solid));
+        assert_eq!(parse(&solid), Ok(expected_triangle()));
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert_eq!(
+            parse(&binary(b"exported", 2, &[triangle([0.0; 3])])),
+            Err("binary STL declares 2 triangles but holds 1".to_string())
+        );
+        assert_eq!(
+            parse(b"solid\nfacet\nouter loop\nvertex 0 0 0\nendloop\nendfacet\n"),
+            Err("facet 1 has 1 vertices, expected 3".to_string())
+        );
+        assert_eq!(
+            parse(b"solid\nfacet normal 0 0"),
+            Err("ASCII STL ends inside a vector".to_string())
+        );
+        assert!(parse(b"short").is_err());
+    }
+}
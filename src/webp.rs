@@ -0,0 +1,808 @@
+//! Minimal lossless WebP (VP8L) encoder.
+//!
+//! Applies the subtract-green transform and codes the pixels with one set of Huffman codes for the
+//! whole image. Backward references only copy runs of the pixel to the left or the row above,
+//! which covers the flat regions of rendered images while keeping the encoder small; there is no
+//! general LZ77 search or color cache.
+
+use std::io::{self, Write};
+
+/// Largest width or height a VP8L image can have.
+pub const MAX_DIMENSION: u32 = 1 << 14;
+
+const SIGNATURE: u8 = 0x2f;
+const SUBTRACT_GREEN_TRANSFORM: u32 = 2;
+
+/// Green alphabet: 256 literals, 24 length prefixes and no color cache entries.
+const GREEN_ALPHABET_SIZE: usize = 256 + 24;
+const ALPHABET_SIZE: usize = 256;
+const DISTANCE_ALPHABET_SIZE: usize = 40;
+
+/// Shortest run worth a backward reference instead of literals.
+const MIN_COPY_LENGTH: usize = 3;
+const MAX_COPY_LENGTH: usize = 4096;
+/// Distance codes for the pixel above and the pixel to the left, from the VP8L distance map.
+const DISTANCE_CODE_ABOVE: u32 = 1;
+const DISTANCE_CODE_LEFT: u32 = 2;
+
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+/// Order in which the code length code lengths are stored.
+const CODE_LENGTH_CODE_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// Writes tightly packed RGBA8 `pixels` as a lossless WebP file.
+///
+/// `width` and `height` must be between 1 and [`MAX_DIMENSION`].
+pub fn encode<W: Write>(mut writer: W, pixels: &[u8], width: u32, height: u32) -> io::Result<()> {
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "WebP images must be between 1x1 and {0}x{0} pixels",
+                MAX_DIMENSION
+            ),
+        ));
+    }
+    assert_eq!(pixels.len(), 4 * width as usize * height as usize);
+
+    let data = encode_vp8l(pixels, width, height);
+
+    // RIFF chunks are padded to an even size.
+    let padding = data.len() % 2;
+    let riff_size = 4 + 8 + data.len() + padding;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(riff_size as u32).to_le_bytes())?;
+    writer.write_all(b"WEBP")?;
+    writer.write_all(b"VP8L")?;
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(&data)?;
+    if padding != 0 {
+        writer.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
+fn encode_vp8l(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+
+    bits.write(u32::from(SIGNATURE), 8);
+    bits.write(width - 1, 14);
+    bits.write(height - 1, 14);
+    let alpha_is_used = pixels.chunks_exact(4).any(|pixel| pixel[3] != 255);
+    bits.write(alpha_is_used as u32, 1);
+    bits.write(0, 3); // version
+
+    bits.write(1, 1); // transform present
+    bits.write(SUBTRACT_GREEN_TRANSFORM, 2);
+    bits.write(0, 1); // no further transforms
+
+    // Pixels as (green, red - green, blue - green, alpha).
+    let symbols: Vec<[u8; 4]> = pixels
+        .chunks_exact(4)
+        .map(|p| [p[1], p[0].wrapping_sub(p[1]), p[2].wrapping_sub(p[1]), p[3]])
+        .collect();
+
+    bits.write(0, 1); // no color cache
+    bits.write(0, 1); // no meta prefix codes
+
+    let tokens = tokenize(&symbols, width as usize);
+
+    let mut histograms = [
+        vec![0u32; GREEN_ALPHABET_SIZE],
+        vec![0u32; ALPHABET_SIZE],
+        vec![0u32; ALPHABET_SIZE],
+        vec![0u32; ALPHABET_SIZE],
+        vec![0u32; DISTANCE_ALPHABET_SIZE],
+    ];
+    for token in &tokens {
+        match *token {
+            Token::Literal(symbol) => {
+                for (histogram, &value) in histograms.iter_mut().zip(&symbol) {
+                    histogram[value as usize] += 1;
+                }
+            }
+            Token::Copy {
+                length,
+                distance_code,
+            } => {
+                histograms[0][256 + prefix_encode(length as u32).0 as usize] += 1;
+                histograms[4][prefix_encode(distance_code).0 as usize] += 1;
+            }
+        }
+    }
+
+    let codes: Vec<PrefixCode> = histograms
+        .iter()
+        .map(|histogram| write_prefix_code(&mut bits, histogram))
+        .collect();
+
+    for token in &tokens {
+        match *token {
+            Token::Literal(symbol) => {
+                for (code, &value) in codes.iter().zip(&symbol) {
+                    code.write_symbol(&mut bits, value as usize);
+                }
+            }
+            Token::Copy {
+                length,
+                distance_code,
+            } => {
+                let (prefix, extra_bits, extra) = prefix_encode(length as u32);
+                codes[0].write_symbol(&mut bits, 256 + prefix as usize);
+                bits.write(extra, extra_bits);
+
+                let (prefix, extra_bits, extra) = prefix_encode(distance_code);
+                codes[4].write_symbol(&mut bits, prefix as usize);
+                bits.write(extra, extra_bits);
+            }
+        }
+    }
+
+    bits.finish()
+}
+
+enum Token {
+    Literal([u8; 4]),
+    /// Repeats the `length` pixels starting at the one `distance_code` refers to.
+    Copy {
+        length: usize,
+        distance_code: u32,
+    },
+}
+
+/// Greedily replaces runs matching the pixel to the left or the row above with copies.
+fn tokenize(symbols: &[[u8; 4]], width: usize) -> Vec<Token> {
+    let run_length = |start: usize, distance: usize| {
+        if start < distance {
+            return 0;
+        }
+        symbols[start..]
+            .iter()
+            .take(MAX_COPY_LENGTH)
+            .zip(&symbols[start - distance..])
+            .take_while(|(a, b)| a == b)
+            .count()
+    };
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < symbols.len() {
+        let left = run_length(i, 1);
+        let above = run_length(i, width);
+        let (length, distance_code) = if above > left {
+            (above, DISTANCE_CODE_ABOVE)
+        } else {
+            (left, DISTANCE_CODE_LEFT)
+        };
+
+        if length >= MIN_COPY_LENGTH {
+            tokens.push(Token::Copy {
+                length,
+                distance_code,
+            });
+            i += length;
+        } else {
+            tokens.push(Token::Literal(symbols[i]));
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Splits a length or distance code into its prefix symbol, extra bit count and extra bits.
+fn prefix_encode(value: u32) -> (u32, u32, u32) {
+    let value = value - 1;
+    if value < 4 {
+        return (value, 0, 0);
+    }
+
+    let highest_bit = 31 - value.leading_zeros();
+    let second_bit = (value >> (highest_bit - 1)) & 1;
+    let extra_bits = highest_bit - 1;
+    (
+        2 * highest_bit + second_bit,
+        extra_bits,
+        value & ((1 << extra_bits) - 1),
+    )
+}
+
+/// LSB-first bit packer.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        debug_assert!(bits <= 32 && u64::from(value) < 1 << bits);
+
+        self.buffer |= u64::from(value) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Canonical Huffman code; symbols are written with their code bits reversed, most significant
+/// bit first into the LSB-first stream.
+struct PrefixCode {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl PrefixCode {
+    /// A code where every symbol takes zero bits, for alphabets with a single used symbol.
+    fn single() -> Self {
+        Self {
+            lengths: Vec::new(),
+            codes: Vec::new(),
+        }
+    }
+
+    fn from_lengths(lengths: Vec<u8>) -> Self {
+        let max_length = lengths.iter().copied().max().unwrap_or(0) as usize;
+
+        let mut length_counts = vec![0u16; max_length + 1];
+        for &length in &lengths {
+            length_counts[length as usize] += 1;
+        }
+        length_counts[0] = 0;
+
+        let mut next_code = vec![0u16; max_length + 1];
+        let mut code = 0;
+        for length in 1..=max_length {
+            code = (code + length_counts[length - 1]) << 1;
+            next_code[length] = code;
+        }
+
+        let codes = lengths
+            .iter()
+            .map(|&length| {
+                if length == 0 {
+                    return 0;
+                }
+                let code = next_code[length as usize];
+                next_code[length as usize] += 1;
+                code.reverse_bits() >> (16 - length)
+            })
+            .collect();
+
+        Self { lengths, codes }
+    }
+
+    fn write_symbol(&self, bits: &mut BitWriter, symbol: usize) {
+        if let Some(&length) = self.lengths.get(symbol) {
+            bits.write(u32::from(self.codes[symbol]), u32::from(length));
+        }
+    }
+}
+
+/// Writes the code for `histogram` and returns it for coding symbols.
+fn write_prefix_code(bits: &mut BitWriter, histogram: &[u32]) -> PrefixCode {
+    let used: Vec<usize> = (0..histogram.len()).filter(|&i| histogram[i] > 0).collect();
+
+    // Simple codes store up to two 8-bit symbols directly.
+    if used.len() <= 2 && used.iter().all(|&symbol| symbol < 256) {
+        bits.write(1, 1); // simple code
+        match *used.as_slice() {
+            [] | [_] => {
+                let symbol = used.first().copied().unwrap_or(0) as u32;
+                bits.write(0, 1); // one symbol
+                if symbol < 2 {
+                    bits.write(0, 1);
+                    bits.write(symbol, 1);
+                } else {
+                    bits.write(1, 1);
+                    bits.write(symbol, 8);
+                }
+                return PrefixCode::single();
+            }
+            [first, second] => {
+                bits.write(1, 1); // two symbols
+                bits.write(1, 1);
+                bits.write(first as u32, 8);
+                bits.write(second as u32, 8);
+
+                let mut lengths = vec![0; histogram.len()];
+                lengths[first] = 1;
+                lengths[second] = 1;
+                return PrefixCode::from_lengths(lengths);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    bits.write(0, 1); // normal code
+    let lengths = huffman_lengths(histogram, MAX_CODE_LENGTH);
+
+    // Code lengths are coded as literals 0..=15 with their own Huffman code.
+    let mut length_histogram = [0u32; 19];
+    for &length in &lengths {
+        length_histogram[length as usize] += 1;
+    }
+    let length_code_lengths = huffman_lengths(&length_histogram, MAX_CODE_LENGTH_CODE_LENGTH);
+    let length_code = if length_histogram.iter().filter(|&&n| n > 0).count() == 1 {
+        // A single used code length is read with zero bits.
+        PrefixCode::single()
+    } else {
+        PrefixCode::from_lengths(length_code_lengths.clone())
+    };
+
+    let stored = CODE_LENGTH_CODE_ORDER
+        .iter()
+        .rposition(|&i| length_code_lengths[i] != 0)
+        .map_or(4, |last| (last + 1).max(4));
+    bits.write(stored as u32 - 4, 4);
+    for &i in &CODE_LENGTH_CODE_ORDER[..stored] {
+        bits.write(u32::from(length_code_lengths[i]), 3);
+    }
+
+    bits.write(0, 1); // lengths for the whole alphabet follow
+    for &length in &lengths {
+        length_code.write_symbol(bits, length as usize);
+    }
+
+    PrefixCode::from_lengths(lengths)
+}
+
+/// Huffman code lengths for `histogram`, limited to `max_length` bits.
+///
+/// Symbols with a zero count get length zero. At least one symbol must be used; a single used
+/// symbol gets length 1.
+fn huffman_lengths(histogram: &[u32], max_length: u8) -> Vec<u8> {
+    let mut counts: Vec<u32> = histogram.to_vec();
+
+    loop {
+        let lengths = unlimited_huffman_lengths(&counts);
+        if lengths.iter().all(|&length| length <= max_length) {
+            return lengths;
+        }
+        // Flatten the distribution until the tree is shallow enough.
+        for count in counts.iter_mut().filter(|count| **count > 0) {
+            *count = (*count / 2).max(1);
+        }
+    }
+}
+
+fn unlimited_huffman_lengths(counts: &[u32]) -> Vec<u8> {
+    // Nodes are (count, symbols below the node); merging two nodes adds a bit to their symbols.
+    let mut nodes: Vec<(u64, Vec<usize>)> = counts
+        .iter()
+        .enumerate()
+        .filter(|&(_, &count)| count > 0)
+        .map(|(symbol, &count)| (u64::from(count), vec![symbol]))
+        .collect();
+
+    let mut lengths = vec![0u8; counts.len()];
+    if nodes.len() == 1 {
+        lengths[nodes[0].1[0]] = 1;
+        return lengths;
+    }
+
+    while nodes.len() > 1 {
+        nodes.sort_by_key(|node| std::cmp::Reverse(node.0));
+        let (count_a, symbols_a) = nodes.pop().unwrap();
+        let (count_b, mut symbols_b) = nodes.pop().unwrap();
+
+        symbols_b.extend(symbols_a);
+        for &symbol in &symbols_b {
+            lengths[symbol] += 1;
+        }
+        nodes.push((count_a + count_b, symbols_b));
+    }
+
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    /// LSB-first bit reader matching [`BitWriter`].
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn new(bytes: &'a [u8]) -> Self {
+            Self { bytes, position: 0 }
+        }
+
+        fn read(&mut self, bits: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..bits {
+                let byte = self.bytes[self.position / 8];
+                value |= u32::from((byte >> (self.position % 8)) & 1) << i;
+                self.position += 1;
+            }
+            value
+        }
+    }
+
+    /// Canonical Huffman decoder built from code lengths, reading codes bit by bit.
+    struct Decoder {
+        /// `(length, code, symbol)`, or a single symbol read with zero bits.
+        codes: Vec<(u8, u16, usize)>,
+        single: Option<usize>,
+    }
+
+    impl Decoder {
+        fn from_lengths(lengths: &[u8]) -> Self {
+            let used: Vec<usize> = (0..lengths.len()).filter(|&i| lengths[i] > 0).collect();
+            if used.len() <= 1 {
+                return Self {
+                    codes: Vec::new(),
+                    single: Some(used.first().copied().unwrap_or(0)),
+                };
+            }
+
+            let mut codes = Vec::new();
+            let mut code = 0u16;
+            for length in 1..=MAX_CODE_LENGTH {
+                for (symbol, _) in lengths.iter().enumerate().filter(|&(_, &l)| l == length) {
+                    codes.push((length, code, symbol));
+                    code += 1;
+                }
+                code <<= 1;
+            }
+            Self {
+                codes,
+                single: None,
+            }
+        }
+
+        fn read_symbol(&self, bits: &mut BitReader) -> usize {
+            if let Some(symbol) = self.single {
+                return symbol;
+            }
+            let mut code = 0u16;
+            for length in 1..=MAX_CODE_LENGTH {
+                code = (code << 1) | bits.read(1) as u16;
+                if let Some(&(_, _, symbol)) = self
+                    .codes
+                    .iter()
+                    .find(|&&(l, c, _)| l == length && c == code)
+                {
+                    return symbol;
+                }
+            }
+            panic!("invalid prefix code");
+        }
+    }
+
+    /// Reads a prefix code header as written by [`write_prefix_code`], returning the code lengths.
+    fn read_code_lengths(bits: &mut BitReader, alphabet_size: usize) -> Vec<u8> {
+        let mut lengths = vec![0u8; alphabet_size];
+
+        if bits.read(1) == 1 {
+            let symbol_count = bits.read(1) + 1;
+            let first_bits = if bits.read(1) == 1 { 8 } else { 1 };
+            let first = bits.read(first_bits) as usize;
+            if symbol_count == 1 {
+                // Zero bits: the length only marks the symbol as used.
+                lengths[first] = 1;
+            } else {
+                let second = bits.read(8) as usize;
+                lengths[first] = 1;
+                lengths[second] = 1;
+            }
+            return lengths;
+        }
+
+        let stored = bits.read(4) as usize + 4;
+        let mut length_code_lengths = [0u8; 19];
+        for &i in &CODE_LENGTH_CODE_ORDER[..stored] {
+            length_code_lengths[i] = bits.read(3) as u8;
+        }
+        let length_code = Decoder::from_lengths(&length_code_lengths);
+        assert_eq!(bits.read(1), 0, "max_symbol is not used by the encoder");
+
+        let mut previous = 8;
+        let mut i = 0;
+        while i < alphabet_size {
+            match length_code.read_symbol(bits) {
+                length @ 0..=15 => {
+                    lengths[i] = length as u8;
+                    if length != 0 {
+                        previous = length as u8;
+                    }
+                    i += 1;
+                }
+                16 => {
+                    for _ in 0..3 + bits.read(2) {
+                        lengths[i] = previous;
+                        i += 1;
+                    }
+                }
+                17 => i += 3 + bits.read(3) as usize,
+                18 => i += 11 + bits.read(7) as usize,
+                _ => unreachable!(),
+            }
+        }
+        lengths
+    }
+
+    fn read_prefix_coded(bits: &mut BitReader, prefix: usize) -> usize {
+        if prefix < 4 {
+            return prefix + 1;
+        }
+        let extra_bits = (prefix as u32 - 2) >> 1;
+        let offset = (2 + (prefix & 1)) << extra_bits;
+        offset + bits.read(extra_bits) as usize + 1
+    }
+
+    /// Decodes the subset of VP8L the encoder produces back into RGBA8 pixels.
+    fn decode(file: &[u8]) -> (u32, u32, bool, Vec<u8>) {
+        assert_eq!(&file[0..4], b"RIFF");
+        let riff_size = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size + 8, file.len());
+        assert_eq!(riff_size % 2, 0);
+        assert_eq!(&file[8..16], b"WEBPVP8L");
+        let data_size = u32::from_le_bytes(file[16..20].try_into().unwrap()) as usize;
+        let mut bits = BitReader::new(&file[20..20 + data_size]);
+
+        assert_eq!(bits.read(8), u32::from(SIGNATURE));
+        let width = bits.read(14) + 1;
+        let height = bits.read(14) + 1;
+        let alpha_is_used = bits.read(1) == 1;
+        assert_eq!(bits.read(3), 0);
+
+        assert_eq!(bits.read(1), 1);
+        assert_eq!(bits.read(2), SUBTRACT_GREEN_TRANSFORM);
+        assert_eq!(bits.read(1), 0);
+        assert_eq!(bits.read(1), 0, "no color cache");
+        assert_eq!(bits.read(1), 0, "no meta prefix codes");
+
+        let decoders: Vec<Decoder> = [
+            GREEN_ALPHABET_SIZE,
+            ALPHABET_SIZE,
+            ALPHABET_SIZE,
+            ALPHABET_SIZE,
+            DISTANCE_ALPHABET_SIZE,
+        ]
+        .iter()
+        .map(|&size| Decoder::from_lengths(&read_code_lengths(&mut bits, size)))
+        .collect();
+
+        let pixel_count = (width * height) as usize;
+        let mut symbols: Vec<[u8; 4]> = Vec::with_capacity(pixel_count);
+        while symbols.len() < pixel_count {
+            let green = decoders[0].read_symbol(&mut bits);
+            if green < 256 {
+                symbols.push([
+                    green as u8,
+                    decoders[1].read_symbol(&mut bits) as u8,
+                    decoders[2].read_symbol(&mut bits) as u8,
+                    decoders[3].read_symbol(&mut bits) as u8,
+                ]);
+                continue;
+            }
+
+            let length = read_prefix_coded(&mut bits, green - 256);
+            let distance_symbol = decoders[4].read_symbol(&mut bits);
+            let distance = match read_prefix_coded(&mut bits, distance_symbol) as u32 {
+                DISTANCE_CODE_ABOVE => width as usize,
+                DISTANCE_CODE_LEFT => 1,
+                code if code > 120 => code as usize - 120,
+                code => panic!("distance code {} is not used by the encoder", code),
+            };
+            for _ in 0..length {
+                symbols.push(symbols[symbols.len() - distance]);
+            }
+        }
+        assert_eq!(symbols.len(), pixel_count, "copies stay within the image");
+
+        let pixels = symbols
+            .iter()
+            .flat_map(|&[g, r, b, a]| vec![r.wrapping_add(g), g, b.wrapping_add(g), a])
+            .collect();
+        (width, height, alpha_is_used, pixels)
+    }
+
+    fn round_trip(pixels: &[u8], width: u32, height: u32) {
+        let mut file = Vec::new();
+        encode(&mut file, pixels, width, height).unwrap();
+
+        let (decoded_width, decoded_height, alpha_is_used, decoded) = decode(&file);
+        assert_eq!((decoded_width, decoded_height), (width, height));
+        assert_eq!(
+            alpha_is_used,
+            pixels.chunks_exact(4).any(|pixel| pixel[3] != 255)
+        );
+        assert!(
+            decoded == pixels,
+            "pixels of {}x{} image differ",
+            width,
+            height
+        );
+    }
+
+    /// Deterministic pseudo-random bytes.
+    fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect()
+    }
+
+    /// Whether the code lengths form a complete prefix code: the Kraft sum is exactly one.
+    fn is_complete(lengths: &[u8]) -> bool {
+        let sum: u64 = lengths
+            .iter()
+            .filter(|&&length| length > 0)
+            .map(|&length| 1u64 << (32 - length))
+            .sum();
+        sum == 1 << 32
+    }
+
+    #[test]
+    fn prefix_encoding() {
+        assert_eq!(prefix_encode(1), (0, 0, 0));
+        assert_eq!(prefix_encode(4), (3, 0, 0));
+        assert_eq!(prefix_encode(5), (4, 1, 0));
+        assert_eq!(prefix_encode(6), (4, 1, 1));
+        assert_eq!(prefix_encode(7), (5, 1, 0));
+        assert_eq!(prefix_encode(4096), (23, 10, 1023));
+    }
+
+    #[test]
+    fn canonical_codes() {
+        let code = PrefixCode::from_lengths(vec![2, 1, 3, 3, 0]);
+
+        // 10, 0, 110 and 111, stored bit-reversed for the LSB-first stream.
+        assert_eq!(code.codes, [0b01, 0b0, 0b011, 0b111, 0]);
+    }
+
+    #[test]
+    fn huffman_lengths_are_complete_and_skip_unused_symbols() {
+        let lengths = huffman_lengths(&[10, 0, 1, 1, 5, 0], MAX_CODE_LENGTH);
+
+        assert_eq!(lengths, [1, 0, 3, 3, 2, 0]);
+        assert!(is_complete(&lengths));
+        assert_eq!(huffman_lengths(&[0, 7, 0], MAX_CODE_LENGTH), [0, 1, 0]);
+    }
+
+    #[test]
+    fn huffman_lengths_respect_the_limit() {
+        // Fibonacci counts make the unlimited tree as deep as there are symbols.
+        let mut histogram = vec![1u32, 1];
+        while histogram.len() < 30 {
+            let next = histogram[histogram.len() - 1] + histogram[histogram.len() - 2];
+            histogram.push(next);
+        }
+        assert!(unlimited_huffman_lengths(&histogram)
+            .iter()
+            .any(|&length| length > MAX_CODE_LENGTH));
+
+        for &max_length in &[MAX_CODE_LENGTH, MAX_CODE_LENGTH_CODE_LENGTH] {
+            let lengths = huffman_lengths(&histogram[..19], max_length);
+            assert!(lengths.iter().all(|&length| length <= max_length));
+            assert!(is_complete(&lengths));
+        }
+        let lengths = huffman_lengths(&histogram, MAX_CODE_LENGTH);
+        assert!(lengths.iter().all(|&length| length <= MAX_CODE_LENGTH));
+        assert!(is_complete(&lengths));
+    }
+
+    #[test]
+    fn simple_code_headers() {
+        // One 8-bit symbol: simple, one symbol, 8-bit, then 200 = 0b11001000.
+        let mut bits = BitWriter::default();
+        let code = write_prefix_code(&mut bits, &histogram(256, &[200]));
+        assert_eq!(bits.finish(), [0b0100_0101, 0b110]);
+        assert!(code.lengths.is_empty(), "a single symbol takes zero bits");
+
+        // One 1-bit symbol: simple, one symbol, 1-bit, then 1.
+        let mut bits = BitWriter::default();
+        write_prefix_code(&mut bits, &histogram(256, &[1]));
+        assert_eq!(bits.finish(), [0b1001]);
+
+        // Two symbols are stored as 8-bit values with one-bit codes.
+        let mut bits = BitWriter::default();
+        let code = write_prefix_code(&mut bits, &histogram(256, &[3, 7]));
+        let written = bits.finish();
+        let lengths = read_code_lengths(&mut BitReader::new(&written), 256);
+        assert_eq!(lengths, code.lengths);
+        assert_eq!((lengths[3], lengths[7]), (1, 1));
+    }
+
+    #[test]
+    fn normal_code_headers() {
+        // Symbols above 255 cannot use a simple code, nor can more than two symbols.
+        let histograms = [
+            histogram(GREEN_ALPHABET_SIZE, &[10, 270]),
+            histogram(ALPHABET_SIZE, &[0, 1, 2]),
+            (0..ALPHABET_SIZE as u32).map(|i| i % 7 + 1).collect(),
+        ];
+
+        for histogram in &histograms {
+            let mut bits = BitWriter::default();
+            let code = write_prefix_code(&mut bits, histogram);
+            let written = bits.finish();
+
+            let mut reader = BitReader::new(&written);
+            assert_eq!(reader.read(1), 0, "normal code");
+            let mut reader = BitReader::new(&written);
+            let lengths = read_code_lengths(&mut reader, histogram.len());
+            assert_eq!(lengths, code.lengths);
+            assert!(is_complete(&lengths));
+        }
+    }
+
+    /// A histogram with a count of one for each of `used`.
+    fn histogram(size: usize, used: &[usize]) -> Vec<u32> {
+        let mut histogram = vec![0; size];
+        for &symbol in used {
+            histogram[symbol] = 1;
+        }
+        histogram
+    }
+
+    #[test]
+    fn known_image() {
+        // A single opaque red pixel: every code is a one-symbol simple code.
+        let mut file = Vec::new();
+        encode(&mut file, &[255, 0, 0, 255], 1, 1).unwrap();
+
+        // 40 header bits, the transform and flags, then four one-symbol codes holding green 0,
+        // red 255, blue 0 and alpha 255 plus an empty distance code; literals take zero bits.
+        let mut expected = b"RIFF\x16\0\0\0WEBPVP8L\x0a\0\0\0".to_vec();
+        expected.extend_from_slice(&[0x2f, 0, 0, 0, 0, 0x45, 0xf4, 0x3f, 0xfa, 0x1f]);
+        assert_eq!(file, expected);
+        round_trip(&[255, 0, 0, 255], 1, 1);
+    }
+
+    #[test]
+    fn round_trips() {
+        // Flat regions, exercising copies from the left and from above.
+        let flat: Vec<u8> = (0..16 * 8)
+            .flat_map(|i| {
+                if (i % 16) < 8 {
+                    vec![10, 20, 30, 255]
+                } else {
+                    vec![200, 100, 50, 255]
+                }
+            })
+            .collect();
+        round_trip(&flat, 16, 8);
+
+        // Repeated rows only match the row above.
+        let rows: Vec<u8> = (0..5)
+            .flat_map(|_| (0..9u8).flat_map(|x| vec![x * 20, 255 - x, x, 128]))
+            .collect();
+        round_trip(&rows, 9, 5);
+
+        // Noise needs normal codes for every channel.
+        round_trip(&noise(13 * 7 * 4, 1), 13, 7);
+        round_trip(&noise(4, 2), 1, 1);
+        round_trip(&vec![0; 300 * 4], 300, 1);
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(encode(Vec::new(), &[], 0, 1).is_err());
+        assert!(encode(Vec::new(), &[], MAX_DIMENSION + 1, 1).is_err());
+    }
+}